@@ -63,7 +63,23 @@ fn benchmark_steps(c: &mut Criterion) {
                         let dprm = make_dprm(vertices).await;
                         // Await the async method immediately.
                         let start = Instant::now();
-                        let _ = dprm.find_blocked_by_obstacle(extra_obstacle.clone()).await;
+                        let _ = dprm.find_blocked_by_obstacle(extra_obstacle).await;
+                        start.elapsed()
+                    });
+                },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("Prm Obstacle Insertion", vertices),
+            &vertices,
+            |bencher, &vertices| {
+                bencher
+                    .to_async(Runtime::new().unwrap()).iter_custom(|_| async {
+                        let mut cfg = cfg(vertices);
+                        cfg.use_viable_edges = true;
+                        let mut prm = Prm::from_cfg(cfg, obstacles()).await;
+                        let start = Instant::now();
+                        prm.add_obstacle(extra_obstacle, THREADS).await;
                         start.elapsed()
                     });
                 },
@@ -85,10 +101,62 @@ fn benchmark_steps(c: &mut Criterion) {
     }
 }
 
+fn random_point(rng: &mut ChaCha8Rng) -> geo::Point {
+    geo::Point::new(rng.gen_range(0.0..WIDTH as f64), rng.gen_range(0.0..HEIGHT as f64))
+}
+
+/// Benchmarks a path query between the vertices nearest to two random points.
+fn bench_query<R: Roadmap>(group: &mut criterion::BenchmarkGroup<criterion::measurement::WallTime>, name: &str, roadmap: &R, vertices: usize) {
+    let mut rng = ChaCha8Rng::from_seed([1u8; 32]);
+    group.bench_with_input(BenchmarkId::new(name, vertices), &vertices, |b, _| {
+        b.iter(|| {
+            let start = roadmap.nearest_vertex(random_point(&mut rng));
+            let end = roadmap.nearest_vertex(random_point(&mut rng));
+            roadmap.find_path(start.index, end.index)
+        });
+    });
+}
+
+fn benchmark_queries(c: &mut Criterion) {
+    let mut group = c.benchmark_group(format!("DPrm vs. Prm A* Query, {} threads", THREADS));
+    for &vertices in &VERTICES_LIST {
+        let obstacles = obstacles();
+        let runtime = Runtime::new().unwrap();
+        let dprm = runtime.block_on(<DPrm as Roadmap>::build(cfg(vertices), obstacles.clone()));
+        bench_query(&mut group, "DPrm A* Query", &dprm, vertices);
+        drop(dprm);
+        let prm = runtime.block_on(<Prm as Roadmap>::build(cfg(vertices), obstacles));
+        bench_query(&mut group, "Prm A* Query", &prm, vertices);
+    }
+}
+
+/// Reports the memory used by the dense storage of the DPrm next to the construction time.
+/// The bytes per edge should stay at BYTES_PER_EDGE for every size.
+fn benchmark_memory(c: &mut Criterion) {
+    let mut group = c.benchmark_group(format!("DPrm Memory, {} threads", THREADS));
+    for &vertices in &VERTICES_LIST {
+        let runtime = Runtime::new().unwrap();
+        let dprm = runtime.block_on(make_dprm(vertices));
+        let memory = dprm.memory();
+        println!(
+            "{} vertices, {} edges: {:.1} MiB, {:.1} bytes per edge ({} expected)",
+            vertices,
+            memory.edges,
+            memory.total_bytes() as f64 / (1024.0 * 1024.0),
+            memory.bytes_per_edge(),
+            BYTES_PER_EDGE
+        );
+        drop(dprm);
+        group.bench_with_input(BenchmarkId::new("DPrm Construction", vertices), &vertices, |b, &vertices| {
+            b.to_async(Runtime::new().unwrap()).iter(|| make_dprm(vertices));
+        });
+    }
+}
+
 // Define the criterion group and criterion main functions
 criterion_group!{
     name = dprm_benchmarks;
     config = Criterion::default().sample_size(10);
-    targets = benchmark_steps
+    targets = benchmark_steps, benchmark_queries, benchmark_memory
 } // benchmark_parallel_prm
 criterion_main!(dprm_benchmarks);
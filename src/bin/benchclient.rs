@@ -0,0 +1,197 @@
+use clap::Parser;
+use dynamic_prm::prelude::*;
+use dynamic_prm::protocol::{Request, Response};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+/// Generates load against a planning server and reports throughput and latency per request type.
+#[derive(Parser, Clone)]
+#[command(version, about)]
+struct Args {
+    addr: String,
+    port: u16,
+    /// Number of concurrent connections
+    num_clients: usize,
+    /// Number of requests sent by each client
+    num_requests: usize,
+    /// Name of the map on the server
+    #[arg(long, default_value = "default")]
+    map: String,
+    /// Fraction of requests that are obstacle updates, the rest are path queries
+    #[arg(long, default_value_t = 0.1, value_parser = parse_ratio)]
+    update_ratio: f64,
+    /// Size of the world, used to pick random points and obstacles
+    #[arg(long, default_value_t = 100.0)]
+    width: f64,
+    #[arg(long, default_value_t = 100.0)]
+    height: f64,
+    #[arg(long, default_value_t = 10.0)]
+    obstacle_max_size: f64,
+    #[arg(long, default_value_t = 1.0)]
+    obstacle_min_size: f64,
+    #[arg(long, default_value_t = 0)]
+    seed: u8,
+}
+
+/// Latencies of successful requests and number of errors, per request type.
+#[derive(Default)]
+struct Measurements {
+    latencies: HashMap<&'static str, Vec<Duration>>,
+    errors: HashMap<&'static str, usize>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let start_time = Instant::now();
+    let mut handles = Vec::new();
+    for client in 0..args.num_clients {
+        let args = args.clone();
+        handles.push(tokio::spawn(async move { run_client(client, args).await }));
+    }
+    let mut total = Measurements::default();
+    for handle in handles {
+        match handle.await? {
+            Ok(measurements) => total.merge(measurements),
+            Err(e) => eprintln!("Client failed: {}", e),
+        }
+    }
+    total.report(start_time.elapsed());
+    Ok(())
+}
+
+/// Sends `num_requests` requests over one connection, waiting for each response.
+/// Each client inserts at most one obstacle at a time and removes it on its next update,
+/// so the obstacle set on the server stays roughly the same size.
+async fn run_client(client: usize, args: Args) -> Result<Measurements, Box<dyn std::error::Error + Send + Sync>> {
+    let stream = TcpStream::connect((args.addr.as_str(), args.port)).await?;
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    // Every client gets its own seed, the client index fills the first bytes
+    let mut seed = [args.seed; 32];
+    for (byte, c) in seed.iter_mut().zip(client.to_le_bytes()) {
+        *byte ^= c;
+    }
+    let mut rng = ChaCha8Rng::from_seed(seed);
+    let mut measurements = Measurements::default();
+    // Keep obstacle ids of different clients apart
+    let mut next_id = (client as ObstacleId + 1) << 64;
+    let mut inserted: Option<ObstacleId> = None;
+
+    for _ in 0..args.num_requests {
+        let (kind, request) = if rng.gen_bool(args.update_ratio) {
+            match inserted.take() {
+                Some(id) => ("remove_obstacle", Request::RemoveObstacle { map: args.map.clone(), id }),
+                None => {
+                    let obstacle = Obstacle::new_random(
+                        &mut rng,
+                        args.obstacle_max_size,
+                        args.obstacle_min_size,
+                        0.0,
+                        0.0,
+                        args.width - 1.0,
+                        args.height - 1.0,
+                        next_id,
+                    );
+                    inserted = Some(next_id);
+                    next_id += 1;
+                    ("add_obstacle", Request::AddObstacle { map: args.map.clone(), obstacle })
+                }
+            }
+        } else {
+            let start = (rng.gen_range(0.0..args.width), rng.gen_range(0.0..args.height));
+            let goal = (rng.gen_range(0.0..args.width), rng.gen_range(0.0..args.height));
+            ("plan", Request::Plan { map: args.map.clone(), start, goal })
+        };
+
+        let mut line = serde_json::to_vec(&request)?;
+        line.push(b'\n');
+        let start_time = Instant::now();
+        writer.write_all(&line).await?;
+        let response = lines.next_line().await?.ok_or("Server closed the connection")?;
+        let latency = start_time.elapsed();
+        match serde_json::from_str::<Response>(&response)? {
+            Response::Error { .. } => *measurements.errors.entry(kind).or_default() += 1,
+            _ => measurements.latencies.entry(kind).or_default().push(latency),
+        }
+    }
+
+    // Leave the map as we found it
+    if let Some(id) = inserted {
+        let mut line = serde_json::to_vec(&Request::RemoveObstacle { map: args.map.clone(), id })?;
+        line.push(b'\n');
+        writer.write_all(&line).await?;
+        lines.next_line().await?;
+    }
+    Ok(measurements)
+}
+
+impl Measurements {
+    fn merge(&mut self, other: Measurements) {
+        for (kind, latencies) in other.latencies {
+            self.latencies.entry(kind).or_default().extend(latencies);
+        }
+        for (kind, errors) in other.errors {
+            *self.errors.entry(kind).or_default() += errors;
+        }
+    }
+
+    /// Prints throughput and latency percentiles per request type.
+    fn report(mut self, elapsed: Duration) {
+        let mut kinds: Vec<&'static str> = self.latencies.keys().chain(self.errors.keys()).copied().collect();
+        kinds.sort();
+        kinds.dedup();
+        let total: usize = self.latencies.values().map(|l| l.len()).sum::<usize>() + self.errors.values().sum::<usize>();
+        println!(
+            "{} requests in {:.3} s, {:.1} requests/s",
+            total,
+            elapsed.as_secs_f64(),
+            total as f64 / elapsed.as_secs_f64()
+        );
+        println!(
+            "{:<16} {:>8} {:>8} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "type", "ok", "errors", "req/s", "p50 ms", "p90 ms", "p99 ms", "max ms"
+        );
+        for kind in kinds {
+            let latencies = self.latencies.entry(kind).or_default();
+            latencies.sort();
+            let errors = self.errors.get(kind).copied().unwrap_or(0);
+            println!(
+                "{:<16} {:>8} {:>8} {:>10.1} {:>10.3} {:>10.3} {:>10.3} {:>10.3}",
+                kind,
+                latencies.len(),
+                errors,
+                (latencies.len() + errors) as f64 / elapsed.as_secs_f64(),
+                percentile(latencies, 0.50),
+                percentile(latencies, 0.90),
+                percentile(latencies, 0.99),
+                percentile(latencies, 1.0),
+            );
+        }
+    }
+}
+
+/// Returns the given percentile of sorted latencies in milliseconds, or 0 if there are none.
+fn percentile(sorted: &[Duration], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let index = ((sorted.len() as f64 * p).ceil() as usize).clamp(1, sorted.len()) - 1;
+    sorted[index].as_secs_f64() * 1000.0
+}
+
+fn parse_ratio(s: &str) -> Result<f64, String> {
+    let ratio = s.trim().parse::<f64>().map_err(|e| e.to_string())?;
+    match (0.0..=1.0).contains(&ratio) {
+        true => Ok(ratio),
+        false => Err(format!("expected a ratio between 0 and 1, got {}", ratio)),
+    }
+}
@@ -0,0 +1,199 @@
+use clap::Parser;
+use dynamic_prm::prelude::*;
+use dynamic_prm::protocol::{Request, Response};
+use geo::Point;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+/// Serves path queries and obstacle updates on named DPrm maps over TCP.
+/// Speaks newline-delimited JSON, see `dynamic_prm::protocol`.
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:7878")]
+    addr: String,
+    /// Map to serve, given as "name=path/to/map.bin". May be repeated.
+    #[arg(long = "map", value_parser = parse_map_arg, required = true)]
+    maps: Vec<(String, String)>,
+    /// Directory that snapshot requests write to. Snapshot requests are refused without it.
+    #[arg(long)]
+    snapshot_dir: Option<PathBuf>,
+}
+
+/// Maps shared between connections.
+/// Queries run on the latest snapshot of a map, so they never wait for updates, see `SharedDPrm`.
+struct Server {
+    maps: HashMap<String, SharedDPrm>,
+    snapshot_dir: Option<PathBuf>,
+}
+
+/// Tells apart the temporary files of snapshots written at the same time.
+static SNAPSHOT_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let mut maps = HashMap::new();
+    for (name, path) in args.maps {
+        let dprm = DPrm::from_file_validated(&path)?;
+        eprintln!("Serving {} from {}", name, path);
+        maps.insert(name, SharedDPrm::new(dprm));
+    }
+    let server = Arc::new(Server { maps, snapshot_dir: args.snapshot_dir });
+
+    let listener = TcpListener::bind(&args.addr).await?;
+    eprintln!("Listening on {}", args.addr);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let server = server.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(stream, server).await {
+                eprintln!("Connection {} closed: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Answers requests from one connection in order, one line per request.
+async fn serve_connection(stream: TcpStream, server: Arc<Server>) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => handle(&server, request).await,
+            Err(e) => error(format!("Invalid request: {}", e)),
+        };
+        let mut out = serde_json::to_vec(&response)?;
+        out.push(b'\n');
+        writer.write_all(&out).await?;
+    }
+    Ok(())
+}
+
+async fn handle(server: &Server, request: Request) -> Response {
+    let Some(map) = server.maps.get(request.map()) else {
+        return error(format!("Unknown map {}", request.map()));
+    };
+    match request {
+        Request::Plan { start, goal, .. } => {
+            let dprm = map.snapshot();
+            blocking(move || {
+                let start = dprm.get_nearest(Point::from(start));
+                let goal = dprm.get_nearest(Point::from(goal));
+                Response::Path {
+                    path: dprm.run_astar(&start.index, &goal.index),
+                    version: dprm.version(),
+                }
+            })
+            .await
+        }
+        Request::Nearest { point, .. } => {
+            let dprm = map.snapshot();
+            blocking(move || Response::Nearest { vertex: dprm.get_nearest(Point::from(point)) }).await
+        }
+        Request::Stats { .. } => {
+            let dprm = map.snapshot();
+            blocking(move || Response::Stats { stats: dprm.stats() }).await
+        }
+        Request::Snapshot { path, .. } => {
+            let Some(dir) = &server.snapshot_dir else {
+                return error("Snapshots are disabled, start the server with --snapshot-dir".to_string());
+            };
+            if !is_file_name(&path) {
+                return error(format!("Snapshot path {} must be a file name in the snapshot directory", path));
+            }
+            let target = dir.join(&path);
+            let temp = dir.join(format!(".{}.{}.tmp", path, SNAPSHOT_COUNTER.fetch_add(1, Ordering::Relaxed)));
+            let dprm = map.snapshot();
+            blocking(move || match write_snapshot(&dprm, &temp, &target) {
+                Ok(()) => Response::Saved { path },
+                Err(e) => error(format!("Failed to write {}: {}", path, e)),
+            })
+            .await
+        }
+        Request::AddObstacle { obstacle, .. } => match map.insert_obstacle(obstacle).await {
+            Some(inserted) => Response::Updated {
+                changed_edges: inserted.value.len(),
+                version: inserted.version,
+            },
+            None => error(format!("Obstacle {} already exists", obstacle.id())),
+        },
+        Request::RemoveObstacle { id, .. } => match map.remove_obstacle(id).await {
+            Some(removed) => Response::Updated {
+                changed_edges: removed.value.len(),
+                version: removed.version,
+            },
+            None => error(format!("Obstacle {} not found", id)),
+        },
+        Request::MoveObstacle { id, rect, .. } => {
+            // Blockings of the moved obstacle are searched before the update, so the move is published
+            // as a single version
+            let moved = Obstacle { rect, id };
+            let blockings = map.snapshot().find_blocked_by_obstacle(moved).await;
+            let updated = map
+                .try_update(|dprm| {
+                    if !dprm.contains_obstacle(id) {
+                        return None;
+                    }
+                    // An edge freed by the removal and blocked again by the insertion did not change
+                    let removed: HashSet<EdgeIndex> = dprm.remove_obstacle(id).into_iter().collect();
+                    let inserted: HashSet<EdgeIndex> = dprm.insert_blocked_by_obstacle(moved, blockings).into_iter().collect();
+                    Some(removed.symmetric_difference(&inserted).count())
+                })
+                .await;
+            match updated {
+                Some(updated) => Response::Updated { changed_edges: updated.value, version: updated.version },
+                None => error(format!("Obstacle {} not found", id)),
+            }
+        }
+    }
+}
+
+/// Runs CPU-heavy work on the blocking pool, so it does not stall the I/O of other connections.
+async fn blocking(work: impl FnOnce() -> Response + Send + 'static) -> Response {
+    match tokio::task::spawn_blocking(work).await {
+        Ok(response) => response,
+        Err(e) => error(format!("Request failed: {}", e)),
+    }
+}
+
+/// Writes the map to a temporary file and renames it over the target, so the target is never left half written.
+fn write_snapshot(dprm: &DPrm, temp: &Path, target: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let result = dprm.to_file(&temp.to_string_lossy()).and_then(|()| Ok(std::fs::rename(temp, target)?));
+    if result.is_err() {
+        let _ = std::fs::remove_file(temp);
+    }
+    result
+}
+
+/// Checks that a snapshot path names a file directly inside the snapshot directory.
+fn is_file_name(path: &str) -> bool {
+    let mut components = Path::new(path).components();
+    matches!((components.next(), components.next()), (Some(Component::Normal(_)), None))
+}
+
+fn error(message: String) -> Response {
+    Response::Error { message }
+}
+
+/// Parses a map given as "name=path".
+fn parse_map_arg(s: &str) -> Result<(String, String), String> {
+    let (name, path) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected \"name=path\", got \"{}\"", s))?;
+    Ok((name.to_string(), path.to_string()))
+}
@@ -0,0 +1,167 @@
+use crate::chunked::ChunkedVec;
+use crate::dprm::serialize_sorted;
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Index of a footprint of a DPrm, 0 is the footprint of `cfg.robot_radius`.
+pub type FootprintIndex = usize;
+
+/// The edges blocked and the vertices contained by one obstacle, for every footprint of a DPrm.
+/// Found by `DPrm::find_blocked_by_obstacle` and applied by `DPrm::insert_blocked_by_obstacle`.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Blockings {
+    /// Blocked edges per footprint, in ascending order.
+    pub edges: Vec<Vec<EdgeIndex>>,
+    /// Contained vertices per footprint, in ascending order.
+    pub vertices: Vec<Vec<VertexIndex>>,
+}
+
+/// The edges and vertices blocked for a robot with the given radius.
+/// Every footprint of a DPrm has its own view on the shared vertices and edges.
+/// Clones share the counters and the lists of every obstacle until they are changed, see SharedDPrm.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub(crate) struct BlockingView {
+    pub(crate) radius: f64,
+    #[serde(serialize_with = "serialize_sorted")]
+    pub(crate) blocked_per_obstacle: HashMap<ObstacleId, Arc<Vec<EdgeIndex>>>,
+    pub(crate) blockings_per_edge: ChunkedVec<u16>,
+    #[serde(serialize_with = "serialize_sorted")]
+    pub(crate) contained_per_obstacle: HashMap<ObstacleId, Arc<Vec<VertexIndex>>>,
+    pub(crate) blockings_per_vertex: ChunkedVec<u16>,
+}
+
+impl BlockingView {
+    /// Creates a view without any obstacles.
+    pub(crate) fn new(radius: f64, num_vertices: usize, num_edges: usize) -> BlockingView {
+        BlockingView {
+            radius,
+            blocked_per_obstacle: HashMap::new(),
+            blockings_per_edge: ChunkedVec::from_elem(0, num_edges),
+            contained_per_obstacle: HashMap::new(),
+            blockings_per_vertex: ChunkedVec::from_elem(0, num_vertices),
+        }
+    }
+
+    pub(crate) fn is_edge_free(&self, edge: EdgeIndex) -> bool {
+        self.blockings_per_edge.get(edge).copied().unwrap_or(0) == 0
+    }
+
+    pub(crate) fn is_vertex_free(&self, vertex: VertexIndex) -> bool {
+        self.blockings_per_vertex.get(vertex).copied().unwrap_or(0) == 0
+    }
+
+    /// Increments a blocking counter, returning true if it was free.
+    fn block(count: &mut u16) -> bool {
+        *count = count.checked_add(1).expect("Too many obstacles block the same edge or vertex");
+        *count == 1
+    }
+
+    /// Records the blockings of an obstacle, returning the newly blocked edges.
+    pub(crate) fn insert(&mut self, oid: ObstacleId, edges: Vec<EdgeIndex>, vertices: Vec<VertexIndex>) -> Vec<EdgeIndex> {
+        let mut newly_blocked_edges = Vec::new();
+        for edge_index in edges.iter() {
+            if Self::block(&mut self.blockings_per_edge[*edge_index]) {
+                newly_blocked_edges.push(*edge_index);
+            }
+        }
+        for vertex in vertices.iter() {
+            Self::block(&mut self.blockings_per_vertex[*vertex]);
+        }
+        self.blocked_per_obstacle.insert(oid, Arc::new(edges));
+        self.contained_per_obstacle.insert(oid, Arc::new(vertices));
+        newly_blocked_edges
+    }
+
+    /// Forgets the blockings of an obstacle, returning the newly freed edges.
+    pub(crate) fn remove(&mut self, oid: ObstacleId) -> Vec<EdgeIndex> {
+        let mut newly_unblocked_edges = Vec::new();
+        for edge_index in self.blocked_per_obstacle.remove(&oid).unwrap_or_default().iter().copied() {
+            let count = &mut self.blockings_per_edge[edge_index];
+            if *count == 0 {
+                // Inconsistent state, see validate
+                println!("Edge {} of obstacle {} is not blocked", edge_index, oid);
+                continue;
+            }
+            *count -= 1;
+            if *count == 0 {
+                newly_unblocked_edges.push(edge_index);
+            }
+        }
+        for vertex in self.contained_per_obstacle.remove(&oid).unwrap_or_default().iter().copied() {
+            let count = &mut self.blockings_per_vertex[vertex];
+            if *count == 0 {
+                println!("Vertex {} of obstacle {} is not blocked", vertex, oid);
+                continue;
+            }
+            *count -= 1;
+        }
+        newly_unblocked_edges
+    }
+
+    /// Checks the blockings against the obstacles and each other, see `DPrm::validate`.
+    pub(crate) fn validate(&self, obstacles: &HashSet<ObstacleId>, num_vertices: usize, num_edges: usize) -> Vec<Inconsistency> {
+        let mut inconsistencies = Vec::new();
+
+        // Obstacles
+        let mut known: Vec<&ObstacleId> = obstacles.iter().collect();
+        known.sort();
+        for oid in known {
+            if !self.blocked_per_obstacle.contains_key(oid) || !self.contained_per_obstacle.contains_key(oid) {
+                inconsistencies.push(Inconsistency::MissingBlockings(*oid));
+            }
+        }
+        let mut oids: Vec<&ObstacleId> = self.blocked_per_obstacle.keys().chain(self.contained_per_obstacle.keys()).collect();
+        oids.sort();
+        oids.dedup();
+        for oid in oids {
+            if !obstacles.contains(oid) {
+                inconsistencies.push(Inconsistency::UnknownObstacle(*oid));
+            }
+        }
+
+        // Edge counters
+        let mut expected_counts = vec![0; num_edges];
+        let mut oids: Vec<&ObstacleId> = self.blocked_per_obstacle.keys().collect();
+        oids.sort();
+        for oid in oids {
+            for edge in self.blocked_per_obstacle[oid].iter() {
+                if *edge < num_edges {
+                    expected_counts[*edge] += 1;
+                } else {
+                    inconsistencies.push(Inconsistency::UnknownEdge { obstacle: *oid, edge: *edge });
+                }
+            }
+        }
+        for edge in 0..num_edges.max(self.blockings_per_edge.len()) {
+            let expected = expected_counts.get(edge).copied().unwrap_or(0);
+            let actual = self.blockings_per_edge.get(edge).copied().unwrap_or(0) as usize;
+            if expected != actual {
+                inconsistencies.push(Inconsistency::BlockingCount { edge, expected, actual });
+            }
+        }
+
+        // Vertex counters
+        let mut expected_counts = vec![0; num_vertices];
+        let mut oids: Vec<&ObstacleId> = self.contained_per_obstacle.keys().collect();
+        oids.sort();
+        for oid in oids {
+            for vertex in self.contained_per_obstacle[oid].iter() {
+                if *vertex < num_vertices {
+                    expected_counts[*vertex] += 1;
+                } else {
+                    inconsistencies.push(Inconsistency::UnknownContainedVertex { obstacle: *oid, vertex: *vertex });
+                }
+            }
+        }
+        for vertex in 0..num_vertices.max(self.blockings_per_vertex.len()) {
+            let expected = expected_counts.get(vertex).copied().unwrap_or(0);
+            let actual = self.blockings_per_vertex.get(vertex).copied().unwrap_or(0) as usize;
+            if expected != actual {
+                inconsistencies.push(Inconsistency::VertexBlockingCount { vertex, expected, actual });
+            }
+        }
+        inconsistencies
+    }
+}
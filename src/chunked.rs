@@ -0,0 +1,90 @@
+use serde::{ser::SerializeSeq, Deserialize, Deserializer, Serialize, Serializer};
+use std::ops::{Index, IndexMut};
+use std::sync::Arc;
+
+/// Number of elements per chunk, a write copies at most this many elements of a shared chunk.
+const CHUNK_LEN: usize = 4096;
+
+/// A vector split into chunks that are shared between clones until written, so that cloning costs
+/// one pointer per chunk and changing a few elements of a clone copies only the chunks they are in.
+/// Serialized as a plain sequence, the same as a Vec.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct ChunkedVec<T> {
+    chunks: Vec<Arc<Vec<T>>>,
+    len: usize,
+}
+
+impl<T: Clone> ChunkedVec<T> {
+    pub(crate) fn from_elem(value: T, len: usize) -> ChunkedVec<T> {
+        let chunks = (0..len.div_ceil(CHUNK_LEN))
+            .map(|i| Arc::new(vec![value.clone(); CHUNK_LEN.min(len - i * CHUNK_LEN)]))
+            .collect();
+        ChunkedVec { chunks, len }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(crate) fn get(&self, index: usize) -> Option<&T> {
+        self.chunks.get(index / CHUNK_LEN).and_then(|chunk| chunk.get(index % CHUNK_LEN))
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &T> {
+        self.chunks.iter().flat_map(|chunk| chunk.iter())
+    }
+
+    /// Elements allocated by the chunks, including chunks shared with clones.
+    pub(crate) fn capacity(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.capacity()).sum()
+    }
+
+    /// Number of chunks that this and the other vector share without a copy.
+    #[cfg(test)]
+    pub(crate) fn shared_chunks(&self, other: &ChunkedVec<T>) -> usize {
+        self.chunks.iter().zip(other.chunks.iter()).filter(|(a, b)| Arc::ptr_eq(a, b)).count()
+    }
+}
+
+impl<T: Clone> From<Vec<T>> for ChunkedVec<T> {
+    fn from(items: Vec<T>) -> ChunkedVec<T> {
+        let len = items.len();
+        let chunks = items.chunks(CHUNK_LEN).map(|chunk| Arc::new(chunk.to_vec())).collect();
+        ChunkedVec { chunks, len }
+    }
+}
+
+impl<T> Index<usize> for ChunkedVec<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        &self.chunks[index / CHUNK_LEN][index % CHUNK_LEN]
+    }
+}
+
+impl<T: Clone> IndexMut<usize> for ChunkedVec<T> {
+    /// Copies the chunk of the element first if it is shared with a clone.
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        &mut Arc::make_mut(&mut self.chunks[index / CHUNK_LEN])[index % CHUNK_LEN]
+    }
+}
+
+impl<T: Serialize + Clone> Serialize for ChunkedVec<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len))?;
+        for item in self.iter() {
+            seq.serialize_element(item)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de, T: Deserialize<'de> + Clone> Deserialize<'de> for ChunkedVec<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ChunkedVec<T>, D::Error> {
+        Vec::deserialize(deserializer).map(ChunkedVec::from)
+    }
+}
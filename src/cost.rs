@@ -0,0 +1,77 @@
+use crate::prelude::*;
+use geo::{BooleanOps, BoundingRect, EuclideanLength, Intersects, Line, LineString, MultiLineString, Polygon};
+use plotters::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub type RegionId = u128;
+
+/// A region that is more expensive to cross, such as a slow zone or a ramp.
+/// Crossing it costs `multiplier` times the length of the part of an edge inside it.
+/// The multiplier is at least 1, so the cost of an edge is never below its length.
+/// Where regions overlap, their extra costs add up.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct CostRegion {
+    pub polygon: Polygon<f64>,
+    pub multiplier: f64,
+    pub id: RegionId,
+}
+
+impl CostRegion {
+    pub fn new(polygon: Polygon<f64>, multiplier: f64, id: RegionId) -> CostRegion {
+        CostRegion { polygon, multiplier, id }
+    }
+
+    /// Returns false for multipliers below 1 or that are not finite,
+    /// which would make the A* heuristic overestimate.
+    pub fn is_valid(&self) -> bool {
+        self.multiplier.is_finite() && self.multiplier >= 1.0
+    }
+
+    /// Extra cost of the edge on top of its length: (multiplier - 1) times the length inside the region, rounded.
+    /// Saturates at `Distance::MAX`, far beyond the 32 bits `DPrm::insert_cost_region` accepts per edge.
+    pub fn extra_cost(&self, edge: &Line<f64>) -> Distance {
+        let inside = match self.polygon.bounding_rect() {
+            Some(bounds) if bounds.intersects(edge) && self.polygon.intersects(edge) => {
+                let line = MultiLineString::new(vec![LineString::from(*edge)]);
+                self.polygon.clip(&line, false).euclidean_length()
+            }
+            _ => 0.0,
+        };
+        ((self.multiplier - 1.0) * inside).round().min(Distance::MAX as f64) as Distance
+    }
+
+    pub fn shape(&self) -> plotters::element::Polygon<(f64, f64)> {
+        plotters::element::Polygon::new(
+            self.polygon.exterior().points().map(|p| p.x_y()).collect::<Vec<_>>(),
+            BLUE.mix(0.2).filled(),
+        )
+    }
+}
+
+/// An obstacle that penalizes rather than blocks the edges crossing it, such as a wet floor.
+/// Every edge that comes within the robot radius of it costs `penalty` more,
+/// and the penalties of overlapping soft obstacles add up.
+/// The extra cost of an edge must fit in 32 bits, `DPrm::insert_soft_obstacle` refuses penalties that would exceed it.
+/// Soft obstacles have their own ids, separate from the obstacles that block edges.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub struct SoftObstacle {
+    pub obstacle: Obstacle,
+    pub penalty: Distance,
+}
+
+impl SoftObstacle {
+    pub fn new(obstacle: Obstacle, penalty: Distance) -> SoftObstacle {
+        SoftObstacle { obstacle, penalty }
+    }
+
+    pub fn id(&self) -> ObstacleId {
+        self.obstacle.id()
+    }
+
+    pub fn rectangle(&self) -> Rectangle<(f64, f64)> {
+        Rectangle::new(
+            [self.obstacle.rect.min().x_y(), self.obstacle.rect.max().x_y()],
+            MAGENTA.mix(0.3).filled(),
+        )
+    }
+}
@@ -1,29 +1,68 @@
 use geo::{EuclideanDistance, Line, Point};
 // use pathfinding::directed::astar::astar;
+use crate::blocking::BlockingView;
+use crate::chunked::ChunkedVec;
+use crate::traffic::TrafficLayer;
+use crate::explain::Grid;
+use crate::transaction::RemovedObstacle;
 use crate::prelude::*;
-use plotters::prelude::*;
+use plotters::{coord::Shift, prelude::*};
 use rand::prelude::*;
 use rand_chacha::ChaCha8Rng;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 use std::{
-    collections::HashMap, f64::consts::PI, fs::File, hash::Hash, io::{BufReader, BufWriter}, sync::Arc
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet}, f64::consts::PI, fs::File, io::{BufReader, BufWriter}, mem::size_of, sync::Arc
 };
 // use serde_json::{from_reader, to_writer_pretty};
 use pathfinding::directed::astar::astar;
 
 const DIMENSIONS: usize = 2;
+/// Number of the shortest segments between the regions that `explain_path` checks for obstacles.
+const MAX_GAP_CHECKS: usize = 64;
+
+/// Bytes of dense storage per edge: the edge itself, its blocking counter and its adjacency entries in both directions.
+/// Does not include the vertices, nor the entries in `blocked_per_obstacle` of blocked edges.
+/// Each footprint after the first adds another counter of 2 bytes, cost regions and soft obstacles add 4 bytes for the extra cost and one-way zones 4 bytes for the counters per direction.
+pub const BYTES_PER_EDGE: usize = size_of::<CompactEdge>() + size_of::<u16>() + 2 * size_of::<(u32, u32)>();
 
 // Prm stores all edges in viable edges
+// Vertices, edges and counters are stored densely, indexed by VertexIndex and EdgeIndex,
+// see BYTES_PER_EDGE for the cost of an edge.
+// The vertices, edges and neighbors never change after construction and are shared by clones, see SharedDPrm.
+// The blockings are kept per footprint, views[0] is the footprint of cfg.robot_radius
+// and is used by all queries that do not name a footprint.
+// Maps are serialized in key order and the thread count is not serialized,
+// so the same roadmap always serializes to the same bytes.
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct DPrm {
-    pub(crate) vertices: HashMap<VertexIndex, Vertex>,
-    pub(crate) edges: Arc<HashMap<EdgeIndex, Edge>>,
+    pub(crate) vertices: Arc<Vec<Point<f64>>>,
+    pub(crate) edges: Arc<Vec<CompactEdge>>,
     // viable_edges: Vec<Edge>,
     obstacles: ObstacleSet,
-    blocked_per_obstacle: HashMap<ObstacleId, Vec<EdgeIndex>>,
-    blockings_per_edge: HashMap<EdgeIndex, usize>,
+    pub(crate) views: Vec<BlockingView>,
+    cost_regions: Vec<CostRegion>,
+    #[serde(serialize_with = "serialize_sorted")]
+    costs_per_region: HashMap<RegionId, Arc<Vec<(EdgeIndex, Distance)>>>,
+    soft_obstacles: Vec<SoftObstacle>,
+    #[serde(serialize_with = "serialize_sorted")]
+    penalized_per_obstacle: HashMap<ObstacleId, Arc<Vec<EdgeIndex>>>,
+    /// Extra cost per edge from the cost regions and soft obstacles, empty while there are none.
+    extra_cost_per_edge: ChunkedVec<u32>,
+    traffic: TrafficLayer,
+    moving_obstacles: Vec<MovingObstacle>,
+    /// Time after which an obstacle is removed by `advance_clock`, only for obstacles that expire.
+    #[serde(serialize_with = "serialize_sorted")]
+    expiry_per_obstacle: HashMap<ObstacleId, Time>,
+    clock: Time,
+    #[serde(serialize_with = "serialize_sorted")]
+    layer_per_obstacle: HashMap<ObstacleId, LayerId>,
+    disabled_layers: BTreeSet<LayerId>,
+    /// Obstacles of disabled layers with their blockings, kept out of the views until the layer is enabled.
+    #[serde(serialize_with = "serialize_sorted")]
+    parked_obstacles: HashMap<ObstacleId, (Obstacle, Arc<Blockings>)>,
+    #[serde(serialize_with = "serialize_without_threads")]
     pub cfg: PrmConfig,
-    neighbors: Neighbors,
+    neighbors: Arc<Adjacency>,
 }
 
 impl DPrm {
@@ -35,15 +74,27 @@ impl DPrm {
     /// Initializes viable edges and vertices.
     /// Finds all blocked edges per obstacle.
     pub async fn from_cfg(cfg: PrmConfig, obstacles: ObstacleSet) -> DPrm {
+        assert!(cfg.num_vertices <= u32::MAX as usize, "At most {} vertices are supported", u32::MAX);
         let mut dprm = DPrm {
-            vertices: HashMap::new(),
-            edges: Arc::new(HashMap::new()),
+            vertices: Arc::new(Vec::new()),
+            edges: Arc::new(Vec::new()),
             // viable_edges: Vec::new(),
             obstacles,
-            blocked_per_obstacle: HashMap::new(),
-            blockings_per_edge: HashMap::new(),
+            views: Vec::new(),
+            cost_regions: Vec::new(),
+            costs_per_region: HashMap::new(),
+            soft_obstacles: Vec::new(),
+            penalized_per_obstacle: HashMap::new(),
+            extra_cost_per_edge: ChunkedVec::default(),
+            traffic: TrafficLayer::default(),
+            moving_obstacles: Vec::new(),
+            expiry_per_obstacle: HashMap::new(),
+            clock: 0.0,
+            layer_per_obstacle: HashMap::new(),
+            disabled_layers: BTreeSet::new(),
+            parked_obstacles: HashMap::new(),
             cfg,
-            neighbors: Neighbors::new(),
+            neighbors: Arc::default(),
         };
         dprm.initialize_viable_edges_and_vertices().await;
         dprm.initialize_all_blocked().await;
@@ -60,7 +111,7 @@ impl DPrm {
         // Serialize `self` using Bincode and write to the file
         bincode::serialize_into(writer, self)?;
 
-        println!("DPrm successfully serialized to {}", file_path);
+        eprintln!("DPrm successfully serialized to {}", file_path);
         Ok(())
     }
 
@@ -73,47 +124,55 @@ impl DPrm {
         // Deserialize the binary data into a `DPrm` instance using Bincode
         let dprm = bincode::deserialize_from(reader)?;
 
-        println!("DPrm successfully deserialized from {}", file_path);
+        eprintln!("DPrm successfully deserialized from {}", file_path);
+        Ok(dprm)
+    }
+
+    /// Reads the DPrm from a binary file like `from_file`, and validates it.
+    /// Returns an error listing every inconsistency if the internal state does not add up.
+    pub fn from_file_validated(file_path: &str) -> Result<DPrm, Box<dyn std::error::Error>> {
+        let dprm = DPrm::from_file(file_path)?;
+        let inconsistencies = dprm.validate();
+        if !inconsistencies.is_empty() {
+            return Err(Box::new(ValidationError(inconsistencies)));
+        }
         Ok(dprm)
     }
 
     /// Generates all the vertices and finds viable edges between them.
     async fn initialize_viable_edges_and_vertices(&mut self) {
-        let (vertices, edges) = self.generate_viable_edges_and_vertices().await;
-        vertices.iter().enumerate().for_each(|(i, v)| {
-            self.vertices.insert(i, v.clone());
-        });
-        let mut edge_map = HashMap::new();
-        edges.iter().enumerate().for_each(|(i, e)| {
-            edge_map.insert(i, e.clone());
-        });
-        self.edges = Arc::new(edge_map);
+        let (vertices, mut edges) = self.generate_viable_edges_and_vertices().await;
+        assert!(edges.len() <= u32::MAX as usize, "At most {} edges are supported", u32::MAX);
+        edges.shrink_to_fit();
+        self.views = vec![BlockingView::new(self.cfg.robot_radius, vertices.len(), edges.len())];
+        self.vertices = vertices;
+        self.edges = Arc::new(edges);
     }
 
-    async fn generate_viable_edges_and_vertices(&self) -> (Vec<Vertex>, Vec<Edge>) {
-        let threads = self.cfg.threads;
-        // Create parallel executors
-        let chunk_size = self.cfg.num_vertices / threads;
+    /// Generates the vertices once and splits the edge search between `cfg.threads()` workers.
+    /// Each worker handles a contiguous range of vertices and the results are joined in order,
+    /// so vertex and edge indices do not depend on the number of threads.
+    async fn generate_viable_edges_and_vertices(&self) -> (Arc<Vec<Point<f64>>>, Vec<CompactEdge>) {
+        let n = self.cfg.num_vertices;
+        let points = Arc::new(self.generate_vertices(n, self.cfg.width, self.cfg.height));
+        let radius = self.max_radius();
+        // Create parallel executors, never more than there are vertices
+        let threads = self.cfg.threads().min(n).max(1);
+        let chunk_size = n.div_ceil(threads);
         let mut handles = Vec::new();
         for i in 0..threads {
             let start = i * chunk_size;
-            let end = if i == threads - 1 {
-                self.cfg.num_vertices
-            } else {
-                (i + 1) * chunk_size
-            };
-            let clone = self.clone();
-            let handle = tokio::spawn(async move { clone.viable_edges_worker(start, end).await });
+            let end = ((i + 1) * chunk_size).min(n);
+            let points = points.clone();
+            let handle = tokio::spawn(Self::viable_edges_worker(points, radius, start, end));
 
             handles.push(handle);
         }
         // Collect all results
-        let mut all_vertices = Vec::new();
         let mut all_viable_edges = Vec::new();
         for handle in handles {
             match handle.await {
-                Ok((vertices, viable_edges)) => {
-                    all_vertices.extend(vertices);
+                Ok(viable_edges) => {
                     all_viable_edges.extend(viable_edges);
                 }
                 Err(e) => {
@@ -122,7 +181,7 @@ impl DPrm {
             }
         }
         println!("Found {} viable edges", all_viable_edges.len());
-        (all_vertices, all_viable_edges)
+        (points, all_viable_edges)
     }
 
     // Generates vertices randomly within the given width and height.
@@ -138,108 +197,145 @@ impl DPrm {
         vertices
     }
 
-    async fn viable_edges_worker(&self, start: usize, end: usize) -> (Vec<Vertex>, Vec<Edge>) {
-        let points = self.generate_vertices(end, self.cfg.width, self.cfg.height);
-        let mut vertices = Vec::new();
+    /// Finds the edges from the vertices in start..end to all vertices with a higher index, ordered by (start, end) vertex.
+    /// Every undirected edge is found once, with start < end, and there are no self-loops.
+    async fn viable_edges_worker(points: Arc<Vec<Point<f64>>>, radius: f64, start: usize, end: usize) -> Vec<CompactEdge> {
         let mut edges = Vec::new();
-        let radius = self.max_radius();
-        println!("start {} end {}", start, end);
         for i in start..end {
             let p1 = points[i];
-            vertices.push(Vertex {
-                point: p1,
-                index: i,
-            });
-            for (j, point) in points.iter().enumerate() {
+            for (j, point) in points.iter().enumerate().skip(i + 1) {
                 let length = p1.euclidean_distance(point);
                 if length < radius {
-                    let line = Line::new(p1, *point);
-                    edges.push(Edge {
-                        line,
-                        length,
-                        points: (i, j),
+                    edges.push(CompactEdge {
+                        from: i as u32,
+                        to: j as u32,
+                        length: length as f32,
                     });
                 }
             }
+            // Yield every 100 vertices, so other tasks can make progress
+            if (i - start) % 100 == 99 {
+                tokio::task::yield_now().await;
+            }
         }
-        (vertices, edges)
+        edges
     }
 
-    /// Updates self to be an accurate representation of all current obstacles.
+    /// Updates self to be an accurate representation of all current obstacles, for every footprint.
     async fn initialize_all_blocked(&mut self) {
         println!("Finding blocked per obstacle...");
-        let mut blocked_per_obstacle: HashMap<ObstacleId, Vec<EdgeIndex>> = HashMap::new();
-        for o in self.obstacles.obstacles.iter() {
-            let blockings = self.find_blocked_by_obstacle(*o).await;
-            blocked_per_obstacle.insert(o.id(), blockings);
+        for view in self.views.iter_mut() {
+            *view = BlockingView::new(view.radius, self.vertices.len(), self.edges.len());
+        }
+        for o in self.obstacles.obstacles.clone() {
+            let blockings = self.find_blocked_by_obstacle(o).await;
+            for (view, (edges, vertices)) in self.views.iter_mut().zip(blockings.edges.into_iter().zip(blockings.vertices)) {
+                view.insert(o.id(), edges, vertices);
+            }
         }
         println!("Found all blocked edges");
-        self.blocked_per_obstacle = blocked_per_obstacle;
-        self.update_blockings();
     }
 
-    fn update_blockings(&mut self) {
-        for blocked in self.blocked_per_obstacle.values() {
-            for edge in blocked {
-                let count = self.blockings_per_edge.entry(*edge).or_insert(0);
-                *count += 1;
-            }
-        }
+    fn is_edge_free(&self, edge: EdgeIndex) -> bool {
+        self.views[0].is_edge_free(edge)
     }
 
+    /// Returns true if the vertex is not inside any obstacle.
+    pub fn is_vertex_free(&self, vertex: VertexIndex) -> bool {
+        self.views[0].is_vertex_free(vertex)
+    }
+
+    /// Returns the free edges in ascending order.
     fn get_all_free_edges(&self) -> Vec<EdgeIndex> {
-        let mut free = Vec::new();
-        for (i, _) in self.edges.iter() {
-            if self.blockings_per_edge.get(i).unwrap_or(&0) == &0 {
-                free.push(*i);
-            }
-        }
-        free
+        (0..self.edges.len()).filter(|e| self.is_edge_free(*e)).collect()
     }
 
+    /// Returns the blocked edges in ascending order.
     fn get_all_blocked(&self) -> Vec<EdgeIndex> {
-        let mut blocked = Vec::new();
-        for edges in self.blocked_per_obstacle.values() {
-            blocked.extend(edges);
-        }
-        blocked.sort();
-        blocked
+        (0..self.edges.len()).filter(|e| !self.is_edge_free(*e)).collect()
     }
 
+    /// Returns true if the obstacle exists, including obstacles of disabled layers.
     pub fn contains_obstacle(&self, oid: ObstacleId) -> bool {
         for o in &self.obstacles.obstacles {
             if o.id() == oid {
                 return true;
             }
         }
-        false
+        self.parked_obstacles.contains_key(&oid)
+    }
+
+    /*
+     *** Footprints ***
+     */
+
+    /// Adds a footprint with the given robot radius, sharing the vertices and edges of the roadmap.
+    /// Finds the blockings of all current obstacles for it, and keeps them up to date from then on.
+    pub async fn add_footprint(&mut self, radius: f64) -> FootprintIndex {
+        let mut view = BlockingView::new(radius, self.vertices.len(), self.edges.len());
+        for o in self.obstacles.obstacles.iter() {
+            let edges = self.find_blocked_for_radii(*o, vec![radius]).await.remove(0);
+            view.insert(o.id(), edges, self.find_contained_by_obstacle(o, radius));
+        }
+        let mut oids: Vec<ObstacleId> = self.parked_obstacles.keys().copied().collect();
+        oids.sort();
+        for oid in oids {
+            let obstacle = self.parked_obstacles[&oid].0;
+            let edges = self.find_blocked_for_radii(obstacle, vec![radius]).await.remove(0);
+            let vertices = self.find_contained_by_obstacle(&obstacle, radius);
+            let blockings = Arc::make_mut(&mut self.parked_obstacles.get_mut(&oid).unwrap().1);
+            blockings.edges.push(edges);
+            blockings.vertices.push(vertices);
+        }
+        self.views.push(view);
+        self.views.len() - 1
+    }
+
+    /// Returns the robot radius of every footprint, indexed by FootprintIndex.
+    pub fn footprints(&self) -> Vec<f64> {
+        self.views.iter().map(|view| view.radius).collect()
     }
 
     /*
      *** Dynamic Updates ***
      */
-    /// Makes no changes to &self, only returns the edge id's blocked by the given obstacle
-    pub async fn find_blocked_by_obstacle(&self, obstacle: Obstacle) -> Vec<EdgeIndex> {
-        let threads = self.cfg.threads;
+    /// Makes no changes to &self, only returns the edges blocked and the vertices contained by the given obstacle,
+    /// for every footprint.
+    pub async fn find_blocked_by_obstacle(&self, obstacle: Obstacle) -> Blockings {
+        let radii = self.footprints();
+        Blockings {
+            vertices: radii.iter().map(|r| self.find_contained_by_obstacle(&obstacle, *r)).collect(),
+            edges: self.find_blocked_for_radii(obstacle, radii).await,
+        }
+    }
+
+    /// Returns the edges blocked by the obstacle inflated by each of the radii.
+    /// Each edge is checked against all radii at once, so the line is only built once.
+    async fn find_blocked_for_radii(&self, obstacle: Obstacle, radii: Vec<f64>) -> Vec<Vec<EdgeIndex>> {
+        let threads = self.cfg.threads();
         let n = self.edges.len();
-        let chunk_size = (n + threads - 1) / threads;
+        let chunk_size = n.div_ceil(threads);
         let mut handles = Vec::new();
         for i in 0..threads {
             let start = i * chunk_size;
             let end = ((i + 1) * chunk_size).min(n);
-            let clone = self.edges.clone();
+            let vertices = self.vertices.clone();
+            let edges = self.edges.clone();
+            let radii = radii.clone();
             let handle =
                 tokio::spawn(
-                    Self::find_blocked_by_obstacle_worker(clone, start, end, obstacle)
+                    Self::find_blocked_by_obstacle_worker(vertices, edges, start, end, obstacle, radii)
                 );
             handles.push(handle);
         }
         // Collect all results
-        let mut blocked_edges = Vec::new();
+        let mut blocked_edges = vec![Vec::new(); radii.len()];
         for handle in handles {
             match handle.await {
                 Ok(edges) => {
-                    blocked_edges.extend(edges);
+                    for (blocked, edges) in blocked_edges.iter_mut().zip(edges) {
+                        blocked.extend(edges);
+                    }
                 }
                 Err(e) => {
                     eprintln!("Error: {:?}", e);
@@ -250,17 +346,21 @@ impl DPrm {
     }
 
     async fn find_blocked_by_obstacle_worker(
-        edges: Arc<HashMap<EdgeIndex, Edge>>,
+        vertices: Arc<Vec<Point<f64>>>,
+        edges: Arc<Vec<CompactEdge>>,
         start: EdgeIndex,
         end: EdgeIndex,
         obstacle: Obstacle,
-    ) -> Vec<EdgeIndex> {
-        let mut blocked = Vec::new();
+        radii: Vec<f64>,
+    ) -> Vec<Vec<EdgeIndex>> {
+        let mut blocked = vec![Vec::new(); radii.len()];
         let mut counter = 0;
         for i in start..end {
-            let edge = &edges[&i];
-            if obstacle.intersects(&edge.line) {
-                blocked.push(i);
+            let line = edges[i].line(&vertices);
+            for (blocked, radius) in blocked.iter_mut().zip(radii.iter()) {
+                if obstacle.intersects_with_clearance(&line, *radius) {
+                    blocked.push(i);
+                }
             }
             counter += 1;
             // Yield every 1000 iterations (adjust as needed)
@@ -271,41 +371,309 @@ impl DPrm {
         blocked
     }
 
-    /// Inserts the given obstacle and updates the graph, returning the newly blocked edges.
-    pub fn insert_blocked_by_obstacle(&mut self, obstacle: Obstacle, blockings: Vec<EdgeIndex>) {
+    /// Makes no changes to &self, only returns the vertices inside the given obstacle inflated by `radius`,
+    /// in ascending order.
+    pub fn find_contained_by_obstacle(&self, obstacle: &Obstacle, radius: f64) -> Vec<VertexIndex> {
+        (0..self.vertices.len())
+            .filter(|v| obstacle.contains_with_clearance(&self.vertices[*v], radius))
+            .collect()
+    }
+
+    /// Inserts the given obstacle and updates the graph of every footprint,
+    /// returning the newly blocked edges of the first footprint.
+    /// The vertices inside the obstacle are blocked as well.
+    /// An obstacle with an id that is already in use is not inserted, neither are blockings
+    /// that were found before a footprint was added.
+    pub fn insert_blocked_by_obstacle(&mut self, obstacle: Obstacle, blockings: Blockings) -> Vec<EdgeIndex> {
+        if self.contains_obstacle(obstacle.id()) {
+            println!("Obstacle {} already exists", obstacle.id());
+            return Vec::new();
+        }
+        if blockings.edges.len() != self.views.len() || blockings.vertices.len() != self.views.len() {
+            println!("Blockings of obstacle {} do not match the {} footprints", obstacle.id(), self.views.len());
+            return Vec::new();
+        }
         self.obstacles.add(obstacle);
         let mut newly_blocked_edges = Vec::new();
-        for edge_index in blockings.iter() {
-            let count = self.blockings_per_edge.entry(*edge_index).or_insert(0);
-            *count += 1;
-            if *count == 1 {
-                newly_blocked_edges.push(*edge_index);
+        for (i, (view, (edges, vertices))) in self.views.iter_mut().zip(blockings.edges.into_iter().zip(blockings.vertices)).enumerate() {
+            let newly_blocked = view.insert(obstacle.id(), edges, vertices);
+            if i == 0 {
+                newly_blocked_edges = newly_blocked;
             }
         }
-        self.blocked_per_obstacle.insert(obstacle.id(), blockings);
-        
-        // Update neighbors
-        for e in newly_blocked_edges {
-            self.neighbors.remove(&self.edges[&e]);
-        }
-    }
-
-    /// Removes obstacle and updates the graph, and returns the newly unblocked edges.
-    pub fn remove_obstacle(&mut self, oid: ObstacleId) {
-        if let Some(unblocked) = self.blocked_per_obstacle.remove(&oid) {
-            for edge_index in unblocked.iter() {
-                let count = self.blockings_per_edge.entry(*edge_index).or_insert(0);
-                *count -= 1;
-                if *count == 0 {
-                    let edge = self.edges[edge_index].clone();
-                    // Update neighbors on the fly
-                    self.neighbors.add(&edge);
+        // The neighbors skip blocked edges and vertices, so they need no update
+        newly_blocked_edges
+    }
+
+    /// Removes obstacle and updates the graph of every footprint,
+    /// and returns the newly unblocked edges of the first footprint.
+    /// The vertices inside the obstacle are freed unless another obstacle contains them.
+    pub fn remove_obstacle(&mut self, oid: ObstacleId) -> Vec<EdgeIndex> {
+        self.expiry_per_obstacle.remove(&oid);
+        self.layer_per_obstacle.remove(&oid);
+        if self.parked_obstacles.remove(&oid).is_some() {
+            // Obstacles of disabled layers block nothing
+            return Vec::new();
+        }
+        if !self.views[0].blocked_per_obstacle.contains_key(&oid) {
+            println!("Obstacle {} not found", oid);
+        }
+        let mut newly_unblocked_edges = Vec::new();
+        for (i, view) in self.views.iter_mut().enumerate() {
+            let newly_unblocked = view.remove(oid);
+            if i == 0 {
+                newly_unblocked_edges = newly_unblocked;
+            }
+        }
+        self.obstacles.remove_by_id(oid);
+        newly_unblocked_edges
+    }
+
+    /*
+     *** Transactions ***
+     */
+
+    /// Starts a what-if transaction, see `Transaction`. Changes made through it are rolled back
+    /// unless it is committed.
+    pub fn begin(&mut self) -> Transaction<'_> {
+        Transaction::new(self)
+    }
+
+    /// Removes the obstacle like `remove_obstacle`, returning everything needed to restore it exactly
+    /// and the newly unblocked edges.
+    pub(crate) fn take_obstacle(&mut self, oid: ObstacleId) -> Option<(RemovedObstacle, Vec<EdgeIndex>)> {
+        let removed = match self.parked_obstacles.get(&oid) {
+            Some((obstacle, blockings)) => RemovedObstacle {
+                obstacle: *obstacle,
+                blockings: Blockings::clone(blockings),
+                position: None,
+                expiry: self.obstacle_expiry(oid),
+                layer: self.obstacle_layer(oid),
+            },
+            None => {
+                let position = self.obstacles.obstacles.iter().position(|o| o.id() == oid)?;
+                RemovedObstacle {
+                    obstacle: self.obstacles.obstacles[position],
+                    blockings: self.blockings_of(oid),
+                    position: Some(position),
+                    expiry: self.obstacle_expiry(oid),
+                    layer: self.obstacle_layer(oid),
                 }
             }
-        } else {
-            println!("Obstacle {} not found", oid);
+        };
+        let newly_unblocked_edges = self.remove_obstacle(oid);
+        Some((removed, newly_unblocked_edges))
+    }
+
+    /// Puts back an obstacle removed by `take_obstacle`, at its old position among the obstacles.
+    pub(crate) fn restore_obstacle(&mut self, removed: RemovedObstacle) {
+        let oid = removed.obstacle.id();
+        match removed.position {
+            Some(position) => {
+                self.insert_blocked_by_obstacle(removed.obstacle, removed.blockings);
+                if let Some(obstacle) = self.obstacles.obstacles.pop() {
+                    self.obstacles.obstacles.insert(position.min(self.obstacles.obstacles.len()), obstacle);
+                }
+            }
+            None => {
+                self.parked_obstacles.insert(oid, (removed.obstacle, Arc::new(removed.blockings)));
+            }
+        }
+        if let Some(expiry) = removed.expiry {
+            self.expiry_per_obstacle.insert(oid, expiry);
+        }
+        if let Some(layer) = removed.layer {
+            self.layer_per_obstacle.insert(oid, layer);
+        }
+    }
+
+    /*
+     *** Layers ***
+     */
+
+    /// Inserts the obstacle like `insert_blocked_by_obstacle` as part of a layer, such as a group of doors.
+    /// If the layer is disabled the obstacle is kept, but blocks nothing until the layer is enabled.
+    pub fn insert_obstacle_in_layer(&mut self, obstacle: Obstacle, blockings: Blockings, layer: LayerId) -> Vec<EdgeIndex> {
+        if self.contains_obstacle(obstacle.id()) {
+            println!("Obstacle {} already exists", obstacle.id());
+            return Vec::new();
+        }
+        let newly_blocked_edges = self.insert_blocked_by_obstacle(obstacle, blockings);
+        if self.contains_obstacle(obstacle.id()) {
+            self.layer_per_obstacle.insert(obstacle.id(), layer);
+            if self.disabled_layers.contains(&layer) {
+                self.park(obstacle.id());
+                return Vec::new();
+            }
+        }
+        newly_blocked_edges
+    }
+
+    /// Lets every obstacle of the layer block again in one pass, returning the newly blocked edges
+    /// of the first footprint in ascending order.
+    pub fn enable_layer(&mut self, layer: LayerId) -> Vec<EdgeIndex> {
+        self.disabled_layers.remove(&layer);
+        let mut oids: Vec<ObstacleId> =
+            self.parked_obstacles.keys().filter(|oid| self.layer_per_obstacle.get(oid) == Some(&layer)).copied().collect();
+        oids.sort();
+        let mut newly_blocked_edges = Vec::new();
+        for oid in oids {
+            newly_blocked_edges.extend(self.unpark(oid));
+        }
+        newly_blocked_edges.sort();
+        newly_blocked_edges
+    }
+
+    /// Stops every obstacle of the layer from blocking in one pass, without forgetting them,
+    /// returning the newly freed edges of the first footprint in ascending order.
+    /// Obstacles inserted into a disabled layer block nothing until it is enabled.
+    pub fn disable_layer(&mut self, layer: LayerId) -> Vec<EdgeIndex> {
+        self.disabled_layers.insert(layer);
+        let mut oids: Vec<ObstacleId> = self
+            .obstacles
+            .obstacles
+            .iter()
+            .map(|o| o.id())
+            .filter(|oid| self.layer_per_obstacle.get(oid) == Some(&layer))
+            .collect();
+        oids.sort();
+        let mut newly_unblocked_edges = Vec::new();
+        for oid in oids {
+            newly_unblocked_edges.extend(self.park(oid));
+        }
+        newly_unblocked_edges.sort();
+        newly_unblocked_edges
+    }
+
+    pub fn is_layer_enabled(&self, layer: LayerId) -> bool {
+        !self.disabled_layers.contains(&layer)
+    }
+
+    pub fn obstacle_layer(&self, oid: ObstacleId) -> Option<LayerId> {
+        self.layer_per_obstacle.get(&oid).copied()
+    }
+
+    /// Returns the obstacles of disabled layers, which block nothing.
+    pub fn disabled_obstacles(&self) -> Vec<Obstacle> {
+        let mut obstacles: Vec<Obstacle> = self.parked_obstacles.values().map(|(o, _)| *o).collect();
+        obstacles.sort_by_key(|o| o.id());
+        obstacles
+    }
+
+    /// Recomputes the blockings of the parked obstacles and parks exactly the obstacles of disabled layers.
+    async fn initialize_layers(&mut self) {
+        let mut oids: Vec<ObstacleId> = self.parked_obstacles.keys().copied().collect();
+        oids.sort();
+        for oid in oids {
+            let obstacle = self.parked_obstacles[&oid].0;
+            let blockings = self.find_blocked_by_obstacle(obstacle).await;
+            self.parked_obstacles.insert(oid, (obstacle, Arc::new(blockings)));
+            if self.layer_per_obstacle.get(&oid).is_none_or(|layer| !self.disabled_layers.contains(layer)) {
+                self.unpark(oid);
+            }
+        }
+        for layer in self.disabled_layers.clone() {
+            self.disable_layer(layer);
+        }
+    }
+
+    /// Moves the blockings of an active obstacle out of the views, returning the newly freed edges.
+    fn park(&mut self, oid: ObstacleId) -> Vec<EdgeIndex> {
+        let Some(obstacle) = self.obstacles.obstacles.iter().find(|o| o.id() == oid).copied() else {
+            return Vec::new();
+        };
+        let blockings = self.blockings_of(oid);
+        let mut newly_unblocked_edges = Vec::new();
+        for (i, view) in self.views.iter_mut().enumerate() {
+            let newly_unblocked = view.remove(oid);
+            if i == 0 {
+                newly_unblocked_edges = newly_unblocked;
+            }
         }
         self.obstacles.remove_by_id(oid);
+        self.parked_obstacles.insert(oid, (obstacle, Arc::new(blockings)));
+        newly_unblocked_edges
+    }
+
+    /// Returns the recorded blockings of an active obstacle for every footprint.
+    fn blockings_of(&self, oid: ObstacleId) -> Blockings {
+        Blockings {
+            edges: self.views.iter().map(|view| view.blocked_per_obstacle.get(&oid).map(|edges| edges.to_vec()).unwrap_or_default()).collect(),
+            vertices: self.views.iter().map(|view| view.contained_per_obstacle.get(&oid).map(|vertices| vertices.to_vec()).unwrap_or_default()).collect(),
+        }
+    }
+
+    /// Moves the blockings of a parked obstacle back into the views, returning the newly blocked edges.
+    fn unpark(&mut self, oid: ObstacleId) -> Vec<EdgeIndex> {
+        match self.parked_obstacles.remove(&oid) {
+            Some((obstacle, blockings)) => self.insert_blocked_by_obstacle(obstacle, Arc::unwrap_or_clone(blockings)),
+            None => Vec::new(),
+        }
+    }
+
+    /*
+     *** Expiry ***
+     */
+
+    /// Inserts the obstacle like `insert_blocked_by_obstacle`, to be removed by the first `advance_clock`
+    /// to a time at or after `expires_at`. The clock is in the caller's units, such as seconds or ticks.
+    pub fn insert_expiring_obstacle(&mut self, obstacle: Obstacle, blockings: Blockings, expires_at: Time) -> Vec<EdgeIndex> {
+        if self.contains_obstacle(obstacle.id()) {
+            println!("Obstacle {} already exists", obstacle.id());
+            return Vec::new();
+        }
+        let newly_blocked_edges = self.insert_blocked_by_obstacle(obstacle, blockings);
+        if self.contains_obstacle(obstacle.id()) {
+            self.expiry_per_obstacle.insert(obstacle.id(), expires_at);
+        }
+        newly_blocked_edges
+    }
+
+    /// Sets or, with None, clears the expiry of an obstacle that is already inserted.
+    pub fn set_obstacle_expiry(&mut self, oid: ObstacleId, expires_at: Option<Time>) {
+        if !self.contains_obstacle(oid) {
+            println!("Obstacle {} not found", oid);
+            return;
+        }
+        match expires_at {
+            Some(expires_at) => self.expiry_per_obstacle.insert(oid, expires_at),
+            None => self.expiry_per_obstacle.remove(&oid),
+        };
+    }
+
+    pub fn obstacle_expiry(&self, oid: ObstacleId) -> Option<Time> {
+        self.expiry_per_obstacle.get(&oid).copied()
+    }
+
+    pub fn clock(&self) -> Time {
+        self.clock
+    }
+
+    /// Moves the clock to `now` and removes every obstacle that expires at or before it in one batch,
+    /// in order of expiry. The clock never moves back, an earlier or non-finite `now` removes nothing.
+    pub fn advance_clock(&mut self, now: Time) -> Expired {
+        if !now.is_finite() {
+            println!("Clock is at {}, not moving it to {}", self.clock, now);
+            return Expired::default();
+        }
+        if now < self.clock {
+            println!("Clock is at {}, not moving it back to {}", self.clock, now);
+            return Expired::default();
+        }
+        self.clock = now;
+        let mut due: Vec<(Time, ObstacleId)> =
+            self.expiry_per_obstacle.iter().filter(|(_, t)| **t <= now).map(|(oid, t)| (*t, *oid)).collect();
+        due.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        let mut expired = Expired::default();
+        for (_, oid) in due {
+            let active = self.obstacles.obstacles.iter().find(|o| o.id() == oid);
+            if let Some(obstacle) = active.or(self.parked_obstacles.get(&oid).map(|(o, _)| o)) {
+                expired.obstacles.push(*obstacle);
+            }
+            expired.freed_edges.extend(self.remove_obstacle(oid));
+        }
+        expired.freed_edges.sort();
+        expired
     }
     
     // /// Inserts new potential vertices and edges into the DPRM and updates the blockings and the graph.
@@ -332,207 +700,1876 @@ impl DPrm {
     // }
 
     /*
-     *** Astar ***
+     *** Cost Regions ***
      */
 
-    /// Initializes the nearest neighbors for efficient Astar execution.
-    /// Should be invoked once the graph is fully initialized for all the obstacle.
-    /// Subsequent calls will overwrite the previous neighbors.
-    /// The mutable insert_blocked_by_obstacle and remove_obstacle functions will update the neighbors.
-    fn initialize_neighbors(&mut self) {
-        for edge_index in self.get_all_free_edges() {
-            self.neighbors.add(&self.edges[&edge_index]);
+    /// Makes no changes to &self, only returns the extra cost of every edge crossing the region,
+    /// in ascending edge order. Edges whose extra cost rounds to 0 are left out.
+    pub async fn find_costs_of_region(&self, region: &CostRegion) -> Vec<(EdgeIndex, Distance)> {
+        let threads = self.cfg.threads();
+        let n = self.edges.len();
+        let chunk_size = n.div_ceil(threads);
+        let mut handles = Vec::new();
+        for i in 0..threads {
+            let start = i * chunk_size;
+            let end = ((i + 1) * chunk_size).min(n);
+            let vertices = self.vertices.clone();
+            let edges = self.edges.clone();
+            let handle = tokio::spawn(Self::find_costs_of_region_worker(vertices, edges, start, end, region.clone()));
+            handles.push(handle);
+        }
+        // Collect all results
+        let mut costs = Vec::new();
+        for handle in handles {
+            match handle.await {
+                Ok(c) => {
+                    costs.extend(c);
+                }
+                Err(e) => {
+                    eprintln!("Error: {:?}", e);
+                }
+            }
         }
+        costs
     }
 
-    /// Runs the A* algorithm on the optimized nearest neighbors structure.
-    pub fn run_astar(&self, start: &VertexIndex, end: &VertexIndex) -> Option<DPrmPath> {
-        if let Some((path, length)) = astar(
-            start,
-            |v| self.successors(&v),
-            |v| self.heuristic(v, end),
-            |v| *v == *end,
-        ) {
-            let mut ret = Vec::new();
-            for i in path {
-                ret.push(self.vertices[&i].clone());
+    async fn find_costs_of_region_worker(
+        vertices: Arc<Vec<Point<f64>>>,
+        edges: Arc<Vec<CompactEdge>>,
+        start: EdgeIndex,
+        end: EdgeIndex,
+        region: CostRegion,
+    ) -> Vec<(EdgeIndex, Distance)> {
+        let mut costs = Vec::new();
+        for i in start..end {
+            let cost = region.extra_cost(&edges[i].line(&vertices));
+            if cost > 0 {
+                costs.push((i, cost));
+            }
+            // Yield every 1000 iterations, so other tasks can make progress
+            if (i - start) % 1000 == 999 {
+                tokio::task::yield_now().await;
             }
-            return Some(DPrmPath {
-                vertices: ret,
-                length,
-            });
         }
-        None
+        costs
     }
 
-    fn successors(&self, start: &VertexIndex) -> Vec<(VertexIndex, Distance)> {
-        // Get the successors
-        self.neighbors.get(start).clone()
+    /// Adds the cost region and the extra costs found by `find_costs_of_region`,
+    /// returning the edges whose cost changed.
+    /// A region with an id that is already in use, with a multiplier below 1, or that would raise
+    /// the extra cost of an edge beyond 32 bits, is not inserted.
+    pub fn insert_cost_region(&mut self, region: CostRegion, costs: Vec<(EdgeIndex, Distance)>) -> Vec<EdgeIndex> {
+        if self.contains_cost_region(region.id) {
+            println!("Cost region {} already exists", region.id);
+            return Vec::new();
+        }
+        if !region.is_valid() {
+            println!("Cost region {} has invalid multiplier {}", region.id, region.multiplier);
+            return Vec::new();
+        }
+        if !self.add_extra_costs(&costs) {
+            println!("Extra costs of cost region {} do not fit in 32 bits", region.id);
+            return Vec::new();
+        }
+        let changed = costs.iter().map(|(edge, _)| *edge).collect();
+        self.costs_per_region.insert(region.id, Arc::new(costs));
+        self.cost_regions.push(region);
+        changed
     }
 
-    fn heuristic(&self, start: &VertexIndex, end: &VertexIndex) -> Distance {
-        // Get the heuristic
-        self.vertices[start]
-            .point
-            .euclidean_distance(&self.vertices[end].point)
-            .round() as Distance
+    /// Removes the cost region, returning the edges whose cost changed.
+    pub fn remove_cost_region(&mut self, id: RegionId) -> Vec<EdgeIndex> {
+        let mut changed = Vec::new();
+        if let Some(costs) = self.costs_per_region.remove(&id) {
+            changed = costs.iter().map(|(edge, _)| *edge).collect();
+            self.subtract_extra_costs(&costs);
+        } else {
+            println!("Cost region {} not found", id);
+        }
+        self.cost_regions.retain(|r| r.id != id);
+        self.release_extra_costs();
+        changed
     }
 
-    /*
-     *** Utilities ***
-     */
-
-    /// Plots the current state of the graph, including vertices, edges, and obstacles.
-    /// If a path is provided, it will also be plotted.
-    /// Saves the plot to a file with the given name.
-    pub fn plot(&self, file_name: String, path: Option<DPrmPath>) {
-        // let filename = format!("output/{}.png", file_name);
-        // Create a drawing area
-        let root = BitMapBackend::new(&file_name, (2000_u32, 2000_u32)).into_drawing_area();
-        root.fill(&WHITE).unwrap();
-
-        // Define the chart
-        let mut chart = ChartBuilder::on(&root)
-            .caption("Edges and Obstacles", ("sans-serif", 50))
-            .x_label_area_size(30)
-            .y_label_area_size(30)
-            .build_cartesian_2d(0.0..(self.cfg.width as f64), 0.0..(self.cfg.height as f64))
-            .unwrap();
-
-        chart.configure_mesh().draw().unwrap();
-
-        // Draw obstacles
-        chart
-            .draw_series(self.obstacles.obstacles.iter().map(|o| o.rectangle()))
-            .unwrap();
-        root.present().unwrap();
-
-        // Draw vertices
-        chart
-            .draw_series(
-                (self.vertices)
-                    .clone()
-                    .into_iter()
-                    .map(|(_, v)| Circle::new(v.point.0.x_y(), 3, BLACK)),
-            )
-            .unwrap();
-
-        // Draw edges
-        chart
-            .draw_series(self.get_all_free_edges().iter().map(|edge_index| {
-                let Edge { line, .. } = &self.edges[edge_index];
-                PathElement::new(vec![line.start.x_y(), line.end.x_y()], CYAN)
-            }))
-            .unwrap()
-            .label("Edge")
-            .legend(|(x, y)| PathElement::new([(x, y), (x + 20, y)], CYAN));
-
-        // Draw blocked edges
-        chart
-            .draw_series(self.get_all_blocked().iter().map(|edge_index| {
-                let edge = &self.edges[edge_index];
-                PathElement::new(vec![edge.line.start.x_y(), edge.line.end.x_y()], YELLOW)
-            }))
-            .unwrap()
-            .label("Edge")
-            .legend(|(x, y)| PathElement::new([(x, y), (x + 20, y)], YELLOW));
-
-        // Draw path
-        if let Some(DPrmPath { vertices, .. }) = path {
-            // Draw edges
-            let mut pv = vertices[0].clone();
-            chart
-                .draw_series(vertices.iter().map(|v| {
-                    let e = PathElement::new(vec![pv.point.x_y(), v.point.x_y()], BLACK);
-                    pv = v.clone();
-                    e
-                }))
-                .unwrap()
-                .label("Edge")
-                .legend(|(x, y)| PathElement::new([(x, y), (x + 20, y)], BLACK));
-        }
+    pub fn contains_cost_region(&self, id: RegionId) -> bool {
+        self.cost_regions.iter().any(|r| r.id == id)
     }
 
-    /// Max length of edges in the graph.
-    fn max_radius(&self) -> f64 {
-        let d = DIMENSIONS as f64;
-        let id = 1.0 / d;
-        let n = self.cfg.num_vertices as f64;
-        let area = self.cfg.width as f64 * self.cfg.height as f64;
-        let mu_free = area * 0.5; // Free space
-        let zeta = PI; // Area of the unit circle
-        let a = 2.0 * (1.0 + 1.0 / d);
-        let b = mu_free / zeta;
-        let gamma = a.powf(id) * b.powf(id);
-        gamma * (n.log(d) / n).powf(id)
+    pub fn cost_regions(&self) -> &[CostRegion] {
+        &self.cost_regions
     }
 
-    /// Returns a random number generator with the seed from the configuration.
-    fn get_rng(&self) -> ChaCha8Rng {
-        ChaCha8Rng::from_seed(self.cfg.seed)
+    /*
+     *** Soft Obstacles ***
+     */
+
+    /// Makes no changes to &self, only returns the edges penalized by the soft obstacle in ascending order.
+    /// These are the edges it would block for the default footprint.
+    pub async fn find_penalized_by_soft_obstacle(&self, soft: &SoftObstacle) -> Vec<EdgeIndex> {
+        self.find_blocked_for_radii(soft.obstacle, vec![self.cfg.robot_radius]).await.remove(0)
     }
 
-    /// Displays the current state of the graph.
-    pub fn print(&self) {
-        let neighbor_edges = self.neighbors.inner.iter().map(|(_, neighbors)| neighbors.len()).sum::<usize>();
-        println!(
-            "Vertices: {}, Edges: {}, Viable Edges: {}, Blocked Edges: {}, Obstacles: {}, Neighbors: {}, Total Neighbor Edges: {}",
-            self.vertices.len(),
-            self.get_all_free_edges().len(),
-            self.edges.len(),
-            self.get_all_blocked().len(),
-            self.obstacles.obstacles.len(),
-            self.neighbors.inner.len(),
-            neighbor_edges
-        );
+    /// Adds the soft obstacle and the penalties of the edges found by `find_penalized_by_soft_obstacle`,
+    /// returning the edges whose cost changed.
+    /// A soft obstacle with an id that is already in use, or that would raise the extra cost of an edge
+    /// beyond 32 bits, is not inserted.
+    pub fn insert_soft_obstacle(&mut self, soft: SoftObstacle, penalized: Vec<EdgeIndex>) -> Vec<EdgeIndex> {
+        if self.contains_soft_obstacle(soft.id()) {
+            println!("Soft obstacle {} already exists", soft.id());
+            return Vec::new();
+        }
+        let costs: Vec<(EdgeIndex, Distance)> = penalized.iter().map(|edge| (*edge, soft.penalty)).collect();
+        if !self.add_extra_costs(&costs) {
+            println!("Penalties of soft obstacle {} do not fit in 32 bits", soft.id());
+            return Vec::new();
+        }
+        let changed = penalized.clone();
+        self.penalized_per_obstacle.insert(soft.id(), Arc::new(penalized));
+        self.soft_obstacles.push(soft);
+        changed
     }
 
-    /// Returns the nearest vertex to the given point.
-    pub fn get_nearest(&self, point: Point<f64>) -> Vertex {
-        let mut min_distance = f64::MAX;
-        let mut nearest: VertexIndex = 0;
-        for (vid, v) in self.vertices.iter() {
-            let distance = v.point.euclidean_distance(&point);
-            if distance < min_distance && self.is_free(&v.point) {
-                min_distance = distance;
-                nearest = *vid;
+    /// Removes the soft obstacle, returning the edges whose cost changed.
+    pub fn remove_soft_obstacle(&mut self, id: ObstacleId) -> Vec<EdgeIndex> {
+        let mut changed = Vec::new();
+        let penalty = self.soft_obstacles.iter().find(|s| s.id() == id).map(|s| s.penalty);
+        match (self.penalized_per_obstacle.remove(&id), penalty) {
+            (Some(penalized), Some(penalty)) => {
+                let costs: Vec<(EdgeIndex, Distance)> = penalized.iter().map(|edge| (*edge, penalty)).collect();
+                self.subtract_extra_costs(&costs);
+                changed = Arc::unwrap_or_clone(penalized);
             }
+            _ => println!("Soft obstacle {} not found", id),
         }
-        self.vertices[&nearest].clone()
+        self.soft_obstacles.retain(|s| s.id() != id);
+        self.release_extra_costs();
+        changed
     }
 
-    pub fn is_free(&self, point: &Point<f64>) -> bool {
-        !self.obstacles.contains(point)
+    pub fn contains_soft_obstacle(&self, id: ObstacleId) -> bool {
+        self.soft_obstacles.iter().any(|s| s.id() == id)
     }
-}
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
-struct Neighbors {
-    inner: HashMap<VertexIndex, Vec<(VertexIndex, Distance)>>,
+    pub fn soft_obstacles(&self) -> &[SoftObstacle] {
+        &self.soft_obstacles
+    }
+
+    /*
+     *** Traffic Rules ***
+     */
+
+    /// Makes no changes to &self, only returns the directions of edges that the zone forbids,
+    /// in ascending edge order.
+    pub async fn find_blocked_by_zone(&self, zone: &DirectionZone) -> Vec<(EdgeIndex, Direction)> {
+        let threads = self.cfg.threads();
+        let n = self.edges.len();
+        let chunk_size = n.div_ceil(threads);
+        let mut handles = Vec::new();
+        for i in 0..threads {
+            let start = i * chunk_size;
+            let end = ((i + 1) * chunk_size).min(n);
+            let vertices = self.vertices.clone();
+            let edges = self.edges.clone();
+            let handle = tokio::spawn(Self::find_blocked_by_zone_worker(vertices, edges, start, end, zone.clone()));
+            handles.push(handle);
+        }
+        // Collect all results
+        let mut blocked = Vec::new();
+        for handle in handles {
+            match handle.await {
+                Ok(b) => {
+                    blocked.extend(b);
+                }
+                Err(e) => {
+                    eprintln!("Error: {:?}", e);
+                }
+            }
+        }
+        blocked
+    }
+
+    async fn find_blocked_by_zone_worker(
+        vertices: Arc<Vec<Point<f64>>>,
+        edges: Arc<Vec<CompactEdge>>,
+        start: EdgeIndex,
+        end: EdgeIndex,
+        zone: DirectionZone,
+    ) -> Vec<(EdgeIndex, Direction)> {
+        let mut blocked = Vec::new();
+        for i in start..end {
+            for direction in zone.blocked_directions(&edges[i].line(&vertices)) {
+                blocked.push((i, direction));
+            }
+            // Yield every 1000 iterations, so other tasks can make progress
+            if (i - start) % 1000 == 999 {
+                tokio::task::yield_now().await;
+            }
+        }
+        blocked
+    }
+
+    /// Adds the one-way zone and the directions found by `find_blocked_by_zone`,
+    /// returning the directions that became blocked.
+    /// A zone with an id that is already in use is not inserted.
+    pub fn insert_direction_zone(&mut self, zone: DirectionZone, blocked: Vec<(EdgeIndex, Direction)>) -> Vec<(EdgeIndex, Direction)> {
+        if self.traffic.contains(zone.id) {
+            println!("Zone {} already exists", zone.id);
+            return Vec::new();
+        }
+        self.traffic.insert(zone, blocked, self.edges.len())
+    }
+
+    /// Removes the one-way zone, returning the directions that are no longer blocked by any zone.
+    pub fn remove_direction_zone(&mut self, id: ZoneId) -> Vec<(EdgeIndex, Direction)> {
+        self.traffic.remove(id)
+    }
+
+    pub fn contains_direction_zone(&self, id: ZoneId) -> bool {
+        self.traffic.contains(id)
+    }
+
+    pub fn direction_zones(&self) -> &[DirectionZone] {
+        &self.traffic.zones
+    }
+
+    /// Switches all one-way rules on or off without forgetting the zones.
+    pub fn set_traffic_rules_enabled(&mut self, enabled: bool) {
+        self.traffic.enabled = enabled;
+    }
+
+    pub fn traffic_rules_enabled(&self) -> bool {
+        self.traffic.enabled
+    }
+
+    /// Recomputes the directions blocked by all zones.
+    async fn initialize_traffic(&mut self) {
+        let zones = std::mem::take(&mut self.traffic.zones);
+        self.traffic = TrafficLayer { enabled: self.traffic.enabled, ..TrafficLayer::default() };
+        for zone in zones {
+            let blocked = self.find_blocked_by_zone(&zone).await;
+            self.insert_direction_zone(zone, blocked);
+        }
+    }
+
+    /*
+     *** Edge Costs ***
+     */
+
+    /// Adds the costs, each edge at most once, returning false and changing nothing
+    /// if the extra cost of any edge would not fit in 32 bits.
+    fn add_extra_costs(&mut self, costs: &[(EdgeIndex, Distance)]) -> bool {
+        let sums: Option<Vec<u32>> = costs
+            .iter()
+            .map(|(edge, cost)| {
+                let extra = self.extra_cost_per_edge.get(*edge).copied().unwrap_or(0);
+                u32::try_from(*cost).ok().and_then(|cost| extra.checked_add(cost))
+            })
+            .collect();
+        let Some(sums) = sums else {
+            return false;
+        };
+        if self.extra_cost_per_edge.is_empty() {
+            self.extra_cost_per_edge = ChunkedVec::from_elem(0, self.edges.len());
+        }
+        for ((edge, _), sum) in costs.iter().zip(sums) {
+            self.extra_cost_per_edge[*edge] = sum;
+        }
+        true
+    }
+
+    fn subtract_extra_costs(&mut self, costs: &[(EdgeIndex, Distance)]) {
+        for (edge, cost) in costs {
+            let extra = &mut self.extra_cost_per_edge[*edge];
+            match u32::try_from(*cost).ok().and_then(|cost| extra.checked_sub(cost)) {
+                Some(remaining) => *extra = remaining,
+                // Inconsistent state, see validate
+                None => println!("Edge {} has a lower extra cost than {}", edge, cost),
+            }
+        }
+    }
+
+    /// Frees the extra costs once there are no cost regions or soft obstacles left.
+    fn release_extra_costs(&mut self) {
+        if self.cost_regions.is_empty() && self.soft_obstacles.is_empty() {
+            self.extra_cost_per_edge = ChunkedVec::default();
+        }
+    }
+
+    /// Recomputes the extra costs of all cost regions and soft obstacles.
+    async fn initialize_costs(&mut self) {
+        self.costs_per_region = HashMap::new();
+        self.penalized_per_obstacle = HashMap::new();
+        self.extra_cost_per_edge = ChunkedVec::default();
+        for region in std::mem::take(&mut self.cost_regions) {
+            let costs = self.find_costs_of_region(&region).await;
+            self.insert_cost_region(region, costs);
+        }
+        for soft in std::mem::take(&mut self.soft_obstacles) {
+            let penalized = self.find_penalized_by_soft_obstacle(&soft).await;
+            self.insert_soft_obstacle(soft, penalized);
+        }
+    }
+
+    /// Cost of traversing the edge: its rounded length plus the extra cost of the regions
+    /// and soft obstacles it crosses.
+    fn edge_cost(&self, edge: EdgeIndex) -> Distance {
+        self.edges[edge].distance() + self.extra_cost_per_edge.get(edge).copied().unwrap_or(0) as Distance
+    }
+
+    /*
+     *** Validation ***
+     */
+
+    /// Checks that the redundant internal state is consistent, returning every inconsistency found.
+    /// `blocked_per_obstacle` and `contained_per_obstacle` are taken as the source of truth for the counters
+    /// and the edges for the neighbors,
+    /// the geometry itself is not rechecked.
+    /// Inconsistencies of footprints other than the first are wrapped in `Inconsistency::Footprint`.
+    pub fn validate(&self) -> Vec<Inconsistency> {
+        let mut inconsistencies = Vec::new();
+
+        // Obstacles
+        let mut seen = HashSet::new();
+        for o in self.obstacles.obstacles.iter() {
+            if !seen.insert(o.id()) {
+                inconsistencies.push(Inconsistency::DuplicateObstacle(o.id()));
+            }
+        }
+
+        // Edges
+        let mut known_vertices = true;
+        for (e, edge) in self.edges.iter().enumerate() {
+            let (v1, v2) = edge.points();
+            for vertex in [v1, v2] {
+                if vertex >= self.vertices.len() {
+                    inconsistencies.push(Inconsistency::UnknownVertex { edge: e, vertex });
+                    known_vertices = false;
+                }
+            }
+        }
+
+        // Blockings per footprint
+        for (footprint, view) in self.views.iter().enumerate() {
+            for inconsistency in view.validate(&seen, self.vertices.len(), self.edges.len()) {
+                inconsistencies.push(match footprint {
+                    0 => inconsistency,
+                    _ => Inconsistency::Footprint { footprint, inconsistency: Box::new(inconsistency) },
+                });
+            }
+        }
+
+        // Cost regions
+        let mut seen_regions = HashSet::new();
+        for r in self.cost_regions.iter() {
+            if !seen_regions.insert(r.id) {
+                inconsistencies.push(Inconsistency::DuplicateRegion(r.id));
+            } else if !self.costs_per_region.contains_key(&r.id) {
+                inconsistencies.push(Inconsistency::MissingCosts(r.id));
+            }
+        }
+        let mut ids: Vec<&RegionId> = self.costs_per_region.keys().collect();
+        ids.sort();
+        let mut expected_costs = vec![0; self.edges.len()];
+        for id in ids {
+            if !seen_regions.contains(id) {
+                inconsistencies.push(Inconsistency::UnknownRegion(*id));
+            }
+            for (edge, cost) in self.costs_per_region[id].iter() {
+                if *edge < self.edges.len() {
+                    expected_costs[*edge] += cost;
+                } else {
+                    inconsistencies.push(Inconsistency::UnknownCostedEdge { region: *id, edge: *edge });
+                }
+            }
+        }
+
+        // Soft obstacles
+        let mut penalties = HashMap::new();
+        for s in self.soft_obstacles.iter() {
+            if penalties.insert(s.id(), s.penalty).is_some() {
+                inconsistencies.push(Inconsistency::DuplicateSoftObstacle(s.id()));
+            } else if !self.penalized_per_obstacle.contains_key(&s.id()) {
+                inconsistencies.push(Inconsistency::MissingPenalties(s.id()));
+            }
+        }
+        let mut oids: Vec<&ObstacleId> = self.penalized_per_obstacle.keys().collect();
+        oids.sort();
+        for oid in oids {
+            let Some(penalty) = penalties.get(oid) else {
+                inconsistencies.push(Inconsistency::UnknownSoftObstacle(*oid));
+                continue;
+            };
+            for edge in self.penalized_per_obstacle[oid].iter() {
+                if *edge < self.edges.len() {
+                    expected_costs[*edge] += penalty;
+                } else {
+                    inconsistencies.push(Inconsistency::UnknownPenalizedEdge { obstacle: *oid, edge: *edge });
+                }
+            }
+        }
+        for edge in 0..self.edges.len().max(self.extra_cost_per_edge.len()) {
+            let expected = expected_costs.get(edge).copied().unwrap_or(0);
+            let actual = self.extra_cost_per_edge.get(edge).copied().unwrap_or(0) as Distance;
+            if expected != actual {
+                inconsistencies.push(Inconsistency::ExtraCost { edge, expected, actual });
+            }
+        }
+
+        // Traffic rules
+        inconsistencies.extend(self.traffic.validate(self.edges.len()));
+
+        // Layers
+        let mut known = seen.clone();
+        let mut oids: Vec<&ObstacleId> = self.parked_obstacles.keys().collect();
+        oids.sort();
+        for oid in oids {
+            if !known.insert(*oid) {
+                inconsistencies.push(Inconsistency::DuplicateObstacle(*oid));
+            }
+            let (_, blockings) = &self.parked_obstacles[oid];
+            if blockings.edges.len() != self.views.len() || blockings.vertices.len() != self.views.len() {
+                inconsistencies.push(Inconsistency::MissingBlockings(*oid));
+            }
+            if !self.layer_per_obstacle.get(oid).is_some_and(|layer| self.disabled_layers.contains(layer)) {
+                inconsistencies.push(Inconsistency::EnabledButParked(*oid));
+            }
+        }
+        let mut oids: Vec<&ObstacleId> = self.layer_per_obstacle.keys().collect();
+        oids.sort();
+        for oid in oids {
+            if !known.contains(oid) {
+                inconsistencies.push(Inconsistency::UnknownLayerObstacle(*oid));
+            } else if seen.contains(oid) && self.disabled_layers.contains(&self.layer_per_obstacle[oid]) {
+                inconsistencies.push(Inconsistency::DisabledButActive(*oid));
+            }
+        }
+
+        // Expiry
+        let mut oids: Vec<&ObstacleId> = self.expiry_per_obstacle.keys().collect();
+        oids.sort();
+        for oid in oids {
+            if !known.contains(oid) {
+                inconsistencies.push(Inconsistency::UnknownExpiry(*oid));
+            }
+        }
+
+        // Moving obstacles
+        let mut seen = HashSet::new();
+        for m in self.moving_obstacles.iter() {
+            if !seen.insert(m.id) {
+                inconsistencies.push(Inconsistency::DuplicateMovingObstacle(m.id));
+            }
+        }
+
+        // Neighbors, as multisets of (to, edge) per vertex
+        if !known_vertices {
+            return inconsistencies;
+        }
+        let expected = Adjacency::new(self.vertices.len(), &self.edges);
+        for from in 0..self.vertices.len() {
+            let mut expected = expected.get(from).to_vec();
+            let mut actual = self.neighbors.get(from).to_vec();
+            expected.sort();
+            actual.sort();
+            let (mut i, mut j) = (0, 0);
+            while i < expected.len() || j < actual.len() {
+                if j == actual.len() || (i < expected.len() && expected[i] < actual[j]) {
+                    inconsistencies.push(Inconsistency::MissingNeighbor { from, to: expected[i].0 as VertexIndex });
+                    i += 1;
+                } else if i == expected.len() || actual[j] < expected[i] {
+                    inconsistencies.push(Inconsistency::UnexpectedNeighbor { from, to: actual[j].0 as VertexIndex });
+                    j += 1;
+                } else {
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        inconsistencies
+    }
+
+    /// Recomputes the blockings of every footprint, the extra costs, the one-way rules and the neighbors
+    /// from the vertices, edges, obstacles, cost regions, soft obstacles and zones.
+    /// Only the first obstacle, cost region, soft obstacle, zone or moving obstacle is kept for ids that occur more than once,
+    /// and exactly the obstacles of disabled layers are kept out of the blockings.
+    pub async fn rebuild_derived_state(&mut self) {
+        let mut seen = HashSet::new();
+        self.obstacles.obstacles.retain(|o| seen.insert(o.id()));
+        self.parked_obstacles.retain(|oid, _| seen.insert(*oid));
+        self.expiry_per_obstacle.retain(|oid, _| seen.contains(oid));
+        self.layer_per_obstacle.retain(|oid, _| seen.contains(oid));
+        let mut seen = HashSet::new();
+        self.cost_regions.retain(|r| seen.insert(r.id));
+        let mut seen = HashSet::new();
+        self.soft_obstacles.retain(|s| seen.insert(s.id()));
+        let mut seen = HashSet::new();
+        self.traffic.zones.retain(|z| seen.insert(z.id));
+        let mut seen = HashSet::new();
+        self.moving_obstacles.retain(|m| seen.insert(m.id));
+        self.initialize_all_blocked().await;
+        self.initialize_layers().await;
+        self.initialize_costs().await;
+        self.initialize_traffic().await;
+        self.initialize_neighbors();
+    }
+
+    /*
+     *** Astar ***
+     */
+
+    /// Initializes the nearest neighbors for efficient Astar execution.
+    /// The neighbors contain every viable edge and only depend on the edges, blocked edges are skipped
+    /// when the successors are looked up, so insert_blocked_by_obstacle and remove_obstacle leave them as is.
+    fn initialize_neighbors(&mut self) {
+        self.neighbors = Arc::new(Adjacency::new(self.vertices.len(), &self.edges));
+    }
+
+    /// Runs the A* algorithm on the optimized nearest neighbors structure.
+    /// There is no path from or to a vertex inside an obstacle.
+    pub fn run_astar(&self, start: &VertexIndex, end: &VertexIndex) -> Option<DPrmPath> {
+        self.run_astar_with_footprint(0, start, end)
+    }
+
+    /// Runs the A* algorithm for the given footprint, see `add_footprint`.
+    /// The length of the path is its cost, including the extra cost of the cost regions it crosses.
+    pub fn run_astar_with_footprint(&self, footprint: FootprintIndex, start: &VertexIndex, end: &VertexIndex) -> Option<DPrmPath> {
+        let view = &self.views[footprint];
+        if !view.is_vertex_free(*start) || !view.is_vertex_free(*end) {
+            return None;
+        }
+        if let Some((path, length)) = astar(
+            start,
+            |v| self.successors(view, v),
+            |v| self.heuristic(v, end),
+            |v| *v == *end,
+        ) {
+            let mut ret = Vec::new();
+            for i in path {
+                ret.push(self.vertex(i));
+            }
+            return Some(DPrmPath {
+                vertices: ret,
+                length,
+            });
+        }
+        None
+    }
+
+    /*
+     *** Diagnostics ***
+     */
+
+    /// Explains why `run_astar` finds no path between start and end: the regions reachable from start
+    /// and able to reach end, the edges leaving the start region with the obstacles blocking them,
+    /// and whether obstacles or a too sparse roadmap separate the regions.
+    /// To tell the two apart, every vertex of the smaller region is paired with the nearest vertex of the other
+    /// that is farther away than the connection radius, found through a grid with cells of that radius,
+    /// and the `MAX_GAP_CHECKS` shortest pairs are checked for obstacles. Vertices are visited by their distance
+    /// to the hull of the other region, so the search stops as soon as no shorter pair can be found.
+    pub fn explain_path(&self, start: &VertexIndex, end: &VertexIndex) -> PathExplanation {
+        let view = &self.views[0];
+        let start_region = Region::new(self.reachable(view, *start, false), &self.vertices);
+        let goal_region = Region::new(self.reachable(view, *end, true), &self.vertices);
+
+        // Edges leaving the start region
+        let mut frontier = Vec::new();
+        for from in start_region.vertices.iter() {
+            for (to, e) in self.neighbors.get(*from).iter() {
+                let (to, edge) = (*to as VertexIndex, *e as EdgeIndex);
+                if start_region.contains(to) {
+                    continue;
+                }
+                frontier.push(FrontierEdge {
+                    edge,
+                    from: *from,
+                    to,
+                    blocked_by: Vec::new(),
+                    to_contained_by: Vec::new(),
+                    one_way: !self.traffic.allows(edge, self.direction(edge, *from)),
+                    into_goal_region: goal_region.contains(to),
+                });
+            }
+        }
+        frontier.sort_by_key(|f| f.edge);
+        let mut frontier_per_vertex: HashMap<VertexIndex, Vec<usize>> = HashMap::new();
+        for (i, f) in frontier.iter().enumerate() {
+            frontier_per_vertex.entry(f.to).or_default().push(i);
+        }
+        let mut oids: Vec<&ObstacleId> = view.blocked_per_obstacle.keys().collect();
+        oids.sort();
+        for oid in oids {
+            for edge in view.blocked_per_obstacle[oid].iter() {
+                if let Ok(i) = frontier.binary_search_by_key(edge, |f| f.edge) {
+                    frontier[i].blocked_by.push(*oid);
+                }
+            }
+            for vertex in view.contained_per_obstacle.get(oid).into_iter().flat_map(|vertices| vertices.iter()) {
+                for i in frontier_per_vertex.get(vertex).into_iter().flatten() {
+                    frontier[*i].to_contained_by.push(*oid);
+                }
+            }
+        }
+
+        let diagnosis = if let Some(vertex) = [*start, *end].into_iter().find(|v| !view.is_vertex_free(*v)) {
+            let mut obstacles: Vec<ObstacleId> =
+                view.contained_per_obstacle.iter().filter(|(_, vs)| vs.contains(&vertex)).map(|(oid, _)| *oid).collect();
+            obstacles.sort();
+            Diagnosis::BlockedEndpoint { vertex, obstacles }
+        } else if start_region.contains(*end) {
+            Diagnosis::Reachable
+        } else {
+            self.find_gap(&start_region, &goal_region)
+        };
+        PathExplanation { start_region, goal_region, frontier, diagnosis }
+    }
+
+    /// Returns the vertices reachable from the vertex, or with `reverse` the vertices that can reach it,
+    /// in ascending order.
+    fn reachable(&self, view: &BlockingView, vertex: VertexIndex, reverse: bool) -> Vec<VertexIndex> {
+        let mut seen = vec![false; self.vertices.len()];
+        seen[vertex] = true;
+        let mut queue = vec![vertex];
+        while let Some(v) = queue.pop() {
+            for (to, e) in self.neighbors.get(v).iter() {
+                let (to, edge) = (*to as VertexIndex, *e as EdgeIndex);
+                let (from, into) = if reverse { (to, v) } else { (v, to) };
+                if seen[to] || !view.is_edge_free(edge) || !view.is_vertex_free(into) || (reverse && !view.is_vertex_free(from)) {
+                    continue;
+                }
+                if self.traffic.allows(edge, self.direction(edge, from)) {
+                    seen[to] = true;
+                    queue.push(to);
+                }
+            }
+        }
+        (0..self.vertices.len()).filter(|v| seen[*v]).collect()
+    }
+
+    /// Direction of traversing the edge starting at the vertex.
+    fn direction(&self, edge: EdgeIndex, from: VertexIndex) -> Direction {
+        match self.edges[edge].from as VertexIndex == from {
+            true => Direction::Forward,
+            false => Direction::Backward,
+        }
+    }
+
+    /// Looks for a segment between the regions that is free of obstacles but longer than the connection radius.
+    fn find_gap(&self, start_region: &Region, goal_region: &Region) -> Diagnosis {
+        let (small, large) = match start_region.vertices.len() <= goal_region.vertices.len() {
+            true => (start_region, goal_region),
+            false => (goal_region, start_region),
+        };
+        let radius = self.max_radius();
+        let grid = Grid::new(&large.vertices, &self.vertices, radius);
+        // Every pair is at least as long as the distance to the hull of the large region,
+        // so visiting the small region in that order lets the search stop once enough shorter pairs are known
+        let mut candidates: Vec<(f64, VertexIndex)> =
+            small.vertices.iter().map(|a| (large.hull.euclidean_distance(&self.vertices[*a]), *a)).collect();
+        candidates.sort_by(|x, y| x.0.total_cmp(&y.0));
+        let mut pairs: Vec<(f64, VertexIndex, VertexIndex)> = Vec::with_capacity(MAX_GAP_CHECKS + 1);
+        for (bound, a) in candidates {
+            let max_length = match pairs.len() {
+                MAX_GAP_CHECKS => pairs[MAX_GAP_CHECKS - 1].0,
+                _ => f64::INFINITY,
+            };
+            if bound >= max_length {
+                break;
+            }
+            if let Some((length, b)) = grid.nearest_beyond(&self.vertices[a], &self.vertices, radius, max_length) {
+                let i = pairs.partition_point(|p| p.0 <= length);
+                pairs.insert(i, (length, a, b));
+                pairs.truncate(MAX_GAP_CHECKS);
+            }
+        }
+        for (length, a, b) in pairs {
+            let segment = Line::new(self.vertices[a], self.vertices[b]);
+            if !self.obstacles.intersects_with_clearance(&segment, self.cfg.robot_radius) {
+                let (from, to) = match start_region.contains(a) {
+                    true => (a, b),
+                    false => (b, a),
+                };
+                return Diagnosis::Sparse { from, to, length };
+            }
+        }
+        Diagnosis::Obstacles
+    }
+
+    /// Runs the A* algorithm as if the temporary obstacles were also inserted, for this query only.
+    /// The roadmap is not changed, so queries with different temporary obstacles can run concurrently.
+    /// Every edge the search reaches is checked against every temporary obstacle, so this suits a few
+    /// keep-out zones per query rather than many.
+    pub fn run_astar_avoiding(&self, start: &VertexIndex, end: &VertexIndex, obstacles: &[Obstacle]) -> Option<DPrmPath> {
+        let radius = self.cfg.robot_radius;
+        self.run_astar_filtered(
+            start,
+            end,
+            |v| !obstacles.iter().any(|o| o.contains_with_clearance(&v.point, radius)),
+            |_, line| !obstacles.iter().any(|o| o.intersects_with_clearance(line, radius)),
+        )
+    }
+
+    /// Runs the A* algorithm using only the vertices and edges that the predicates allow, for this query only.
+    /// Both predicates are only asked about vertices and edges that are free in the roadmap.
+    /// The roadmap is not changed, so queries with different predicates can run concurrently.
+    pub fn run_astar_filtered<V, E>(&self, start: &VertexIndex, end: &VertexIndex, allow_vertex: V, allow_edge: E) -> Option<DPrmPath>
+    where
+        V: Fn(&Vertex) -> bool,
+        E: Fn(EdgeIndex, &Line<f64>) -> bool,
+    {
+        let view = &self.views[0];
+        if !view.is_vertex_free(*start) || !view.is_vertex_free(*end) {
+            return None;
+        }
+        if !allow_vertex(&self.vertex(*start)) || !allow_vertex(&self.vertex(*end)) {
+            return None;
+        }
+        let (path, length) = astar(
+            start,
+            |v| {
+                self.traversable(view, *v)
+                    .filter(|(to, e)| allow_edge(*e, &self.edges[*e].line(&self.vertices)) && allow_vertex(&self.vertex(*to)))
+                    .map(|(to, e)| (to, self.edge_cost(e)))
+                    .collect::<Vec<_>>()
+            },
+            |v| self.heuristic(v, end),
+            |v| *v == *end,
+        )?;
+        Some(DPrmPath {
+            vertices: path.into_iter().map(|i| self.vertex(i)).collect(),
+            length,
+        })
+    }
+
+    fn successors(&self, view: &BlockingView, start: &VertexIndex) -> Vec<(VertexIndex, Distance)> {
+        self.traversable(view, *start).map(|(to, e)| (to, self.edge_cost(e))).collect()
+    }
+
+    /// Returns the (to, edge) pairs reachable from the vertex over free edges to free vertices,
+    /// in a direction the traffic rules allow.
+    fn traversable<'a>(&'a self, view: &'a BlockingView, start: VertexIndex) -> impl Iterator<Item = (VertexIndex, EdgeIndex)> + 'a {
+        self.neighbors
+            .get(start)
+            .iter()
+            .map(|(to, e)| (*to as VertexIndex, *e as EdgeIndex))
+            .filter(move |(to, e)| view.is_edge_free(*e) && view.is_vertex_free(*to))
+            .filter(move |(_, e)| {
+                self.traffic.allows(*e, self.direction(*e, start))
+            })
+    }
+
+    /*
+     *** Moving Obstacles ***
+     */
+
+    /// Adds an obstacle with a known trajectory. It does not block any edges,
+    /// only `run_timed_astar` avoids it, at the times it is in the way.
+    /// An obstacle with an id that is already in use, or with waypoints out of time order, is not added.
+    pub fn add_moving_obstacle(&mut self, obstacle: MovingObstacle) {
+        if self.moving_obstacles.iter().any(|m| m.id == obstacle.id) {
+            println!("Moving obstacle {} already exists", obstacle.id);
+            return;
+        }
+        if obstacle.trajectory.windows(2).any(|w| w[0].0 >= w[1].0) {
+            println!("Moving obstacle {} has waypoints out of time order", obstacle.id);
+            return;
+        }
+        self.moving_obstacles.push(obstacle);
+    }
+
+    pub fn remove_moving_obstacle(&mut self, id: ObstacleId) {
+        if !self.moving_obstacles.iter().any(|m| m.id == id) {
+            println!("Moving obstacle {} not found", id);
+        }
+        self.moving_obstacles.retain(|m| m.id != id);
+    }
+
+    pub fn moving_obstacles(&self) -> &[MovingObstacle] {
+        &self.moving_obstacles
+    }
+
+    /// Returns true if no moving obstacle comes within the robot radius of the point at the given step.
+    fn is_free_at(&self, point: &Point<f64>, query: &TimedQuery, step: u32) -> bool {
+        let time = query.time(step);
+        !self.moving_obstacles.iter().any(|m| m.contains_with_clearance(point, time, self.cfg.robot_radius))
+    }
+
+    /// Plans the fastest path in (vertex, time) space for a robot moving at `query.speed`,
+    /// avoiding the static obstacles as `run_astar` does and the moving obstacles at the times they are in the way.
+    /// The robot may wait at a vertex for whole time steps. Returns None if the end cannot be reached
+    /// within the horizon, or if the query is not valid.
+    pub fn run_timed_astar(&self, start: &VertexIndex, end: &VertexIndex, query: &TimedQuery) -> Option<TimedPath> {
+        if !query.is_valid() {
+            println!("Invalid timed query {:?}", query);
+            return None;
+        }
+        let view = &self.views[0];
+        if !view.is_vertex_free(*start) || !view.is_vertex_free(*end) || !self.is_free_at(&self.vertices[*start], query, 0) {
+            return None;
+        }
+        let max_step = (query.horizon / query.time_step).floor() as u32;
+        let (path, _) = astar(
+            &(*start, 0),
+            |(v, step)| self.timed_successors(view, query, *v, *step, max_step),
+            // Counted in u64, since a single step count may already be u32::MAX
+            |(v, _)| query.steps(self.vertices[*v].euclidean_distance(&self.vertices[*end])) as u64,
+            |(v, _)| *v == *end,
+        )?;
+        // Keep the first and last step of every wait
+        let mut vertices: Vec<TimedVertex> = Vec::new();
+        for (i, (v, step)) in path.iter().enumerate() {
+            let waits = i > 0 && path[i - 1].0 == *v && i + 1 < path.len() && path[i + 1].0 == *v;
+            if !waits {
+                vertices.push(TimedVertex { vertex: self.vertex(*v), time: query.time(*step) });
+            }
+        }
+        let arrival = query.time(path.last()?.1);
+        Some(TimedPath { vertices, arrival })
+    }
+
+    fn timed_successors(&self, view: &BlockingView, query: &TimedQuery, v: VertexIndex, step: u32, max_step: u32) -> Vec<((VertexIndex, u32), u64)> {
+        let mut successors = Vec::new();
+        if step < max_step && self.is_free_at(&self.vertices[v], query, step + 1) {
+            successors.push(((v, step + 1), 1));
+        }
+        for (to, _) in self.traversable(view, v) {
+            let line = Line::new(self.vertices[v], self.vertices[to]);
+            let steps = query.steps(self.vertices[v].euclidean_distance(&self.vertices[to])).max(1);
+            let arrival = match step.checked_add(steps) {
+                Some(arrival) if arrival <= max_step => arrival,
+                _ => continue,
+            };
+            if (1..=steps).all(|k| self.is_free_at(&query.position(&line, k, steps), query, step + k)) {
+                successors.push(((to, arrival), steps as u64));
+            }
+        }
+        successors
+    }
+
+    fn heuristic(&self, start: &VertexIndex, end: &VertexIndex) -> Distance {
+        // Get the heuristic
+        self.vertices[*start]
+            .euclidean_distance(&self.vertices[*end])
+            .round() as Distance
+    }
+
+    /*
+     *** Utilities ***
+     */
+
+    /// Plots the current state of the graph, including vertices, edges, and obstacles.
+    /// If a path is provided, it will also be plotted.
+    /// Saves the plot to a file with the given name.
+    pub fn plot(&self, file_name: String, path: Option<DPrmPath>) {
+        // let filename = format!("output/{}.png", file_name);
+        // Create a drawing area
+        let root = BitMapBackend::new(&file_name, (2000_u32, 2000_u32)).into_drawing_area();
+        self.draw(&root, "Edges and Obstacles", path.as_ref(), &[], &[])
+            .unwrap();
+        root.present().unwrap();
+    }
+
+    /// Draws the current state of the graph onto the given drawing area.
+    /// Edges in `newly_blocked` and `newly_freed` are highlighted on top of the regular edges.
+    /// Does not present the drawing area, that is left to the caller.
+    pub(crate) fn draw<DB: DrawingBackend>(
+        &self,
+        root: &DrawingArea<DB, Shift>,
+        caption: &str,
+        path: Option<&DPrmPath>,
+        newly_blocked: &[EdgeIndex],
+        newly_freed: &[EdgeIndex],
+    ) -> Result<(), Box<dyn std::error::Error>>
+    where
+        DB::ErrorType: 'static,
+    {
+        root.fill(&WHITE)?;
+
+        // Define the chart
+        let mut chart = ChartBuilder::on(root)
+            .caption(caption, ("sans-serif", 50))
+            .x_label_area_size(30)
+            .y_label_area_size(30)
+            .build_cartesian_2d(0.0..(self.cfg.width as f64), 0.0..(self.cfg.height as f64))?;
+
+        chart.configure_mesh().draw()?;
+
+        // Draw cost regions and zones below the obstacles
+        chart.draw_series(self.cost_regions.iter().map(|r| r.shape()))?;
+        chart.draw_series(self.traffic.zones.iter().map(|z| z.shape()))?;
+        chart.draw_series(self.moving_obstacles.iter().map(|m| m.path()))?;
+
+        // Draw soft obstacles and obstacles
+        chart.draw_series(self.soft_obstacles.iter().map(|s| s.rectangle()))?;
+        chart.draw_series(self.obstacles.obstacles.iter().map(|o| o.rectangle()))?;
+
+        // Draw the vertices outside obstacles
+        chart.draw_series(
+            self.vertices
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| self.is_vertex_free(*i))
+                .map(|(_, v)| Circle::new(v.x_y(), 3, BLACK)),
+        )?;
+
+        // Draw edges
+        chart
+            .draw_series(self.get_all_free_edges().iter().map(|edge_index| {
+                let line = self.edges[*edge_index].line(&self.vertices);
+                PathElement::new(vec![line.start.x_y(), line.end.x_y()], CYAN)
+            }))?
+            .label("Edge")
+            .legend(|(x, y)| PathElement::new([(x, y), (x + 20, y)], CYAN));
+
+        // Draw blocked edges
+        chart
+            .draw_series(self.get_all_blocked().iter().map(|edge_index| {
+                let line = self.edges[*edge_index].line(&self.vertices);
+                PathElement::new(vec![line.start.x_y(), line.end.x_y()], YELLOW)
+            }))?
+            .label("Edge")
+            .legend(|(x, y)| PathElement::new([(x, y), (x + 20, y)], YELLOW));
+
+        // Highlight the edges that changed in the last update
+        chart.draw_series(newly_blocked.iter().map(|edge_index| {
+            let line = self.edges[*edge_index].line(&self.vertices);
+            PathElement::new(vec![line.start.x_y(), line.end.x_y()], RED.stroke_width(2))
+        }))?;
+        chart.draw_series(newly_freed.iter().map(|edge_index| {
+            let line = self.edges[*edge_index].line(&self.vertices);
+            PathElement::new(vec![line.start.x_y(), line.end.x_y()], GREEN.stroke_width(2))
+        }))?;
+
+        // Draw path
+        if let Some(DPrmPath { vertices, .. }) = path {
+            // Draw edges
+            let mut pv = vertices[0].clone();
+            chart
+                .draw_series(vertices.iter().map(|v| {
+                    let e = PathElement::new(vec![pv.point.x_y(), v.point.x_y()], BLACK);
+                    pv = v.clone();
+                    e
+                }))?
+                .label("Edge")
+                .legend(|(x, y)| PathElement::new([(x, y), (x + 20, y)], BLACK));
+        }
+        Ok(())
+    }
+
+    /// Max length of edges in the graph.
+    pub(crate) fn max_radius(&self) -> f64 {
+        let d = DIMENSIONS as f64;
+        let id = 1.0 / d;
+        let n = self.cfg.num_vertices as f64;
+        let area = self.cfg.width as f64 * self.cfg.height as f64;
+        let mu_free = area * 0.5; // Free space
+        let zeta = PI; // Area of the unit circle
+        let a = 2.0 * (1.0 + 1.0 / d);
+        let b = mu_free / zeta;
+        let gamma = a.powf(id) * b.powf(id);
+        gamma * (n.log(d) / n).powf(id)
+    }
+
+    /// Returns a random number generator with the seed from the configuration.
+    fn get_rng(&self) -> ChaCha8Rng {
+        ChaCha8Rng::from_seed(self.cfg.seed)
+    }
+
+    /// Returns counts describing the current state of the graph.
+    pub fn stats(&self) -> DPrmStats {
+        DPrmStats {
+            vertices: self.vertices.len(),
+            free_edges: self.get_all_free_edges().len(),
+            viable_edges: self.edges.len(),
+            blocked_edges: self.get_all_blocked().len(),
+            blocked_vertices: (0..self.vertices.len()).filter(|v| !self.is_vertex_free(*v)).count(),
+            obstacles: self.obstacles.obstacles.len(),
+            cost_regions: self.cost_regions.len(),
+            soft_obstacles: self.soft_obstacles.len(),
+            direction_zones: self.traffic.zones.len(),
+            moving_obstacles: self.moving_obstacles.len(),
+            disabled_obstacles: self.parked_obstacles.len(),
+            neighbors: (0..self.vertices.len()).filter(|v| !self.successors(&self.views[0], v).is_empty()).count(),
+            neighbor_edges: (0..self.vertices.len()).map(|v| self.successors(&self.views[0], &v).len()).sum::<usize>(),
+        }
+    }
+
+    /// Returns the bytes used by the dense storage of the graph.
+    pub fn memory(&self) -> DPrmMemory {
+        DPrmMemory {
+            edges: self.edges.len(),
+            vertex_bytes: self.vertices.capacity() * size_of::<Point<f64>>()
+                + self.views.iter().map(|view| view.blockings_per_vertex.capacity() * size_of::<u16>()).sum::<usize>()
+                + self.neighbors.offsets.capacity() * size_of::<usize>(),
+            edge_bytes: self.edges.capacity() * size_of::<CompactEdge>(),
+            counter_bytes: self.views.iter().map(|view| view.blockings_per_edge.capacity() * size_of::<u16>()).sum::<usize>()
+                + self.extra_cost_per_edge.capacity() * size_of::<u32>()
+                + (self.traffic.blockings_forward.capacity() + self.traffic.blockings_backward.capacity()) * size_of::<u16>(),
+            neighbor_bytes: self.neighbors.entries.capacity() * size_of::<(u32, u32)>(),
+        }
+    }
+
+    /// Displays the current state of the graph.
+    pub fn print(&self) {
+        let stats = self.stats();
+        println!(
+            "Vertices: {}, Free Edges: {}, Viable Edges: {}, Blocked Edges: {}, Blocked Vertices: {}, Obstacles: {}, Cost Regions: {}, Soft Obstacles: {}, Direction Zones: {}, Moving Obstacles: {}, Disabled Obstacles: {}, Vertices With Neighbors: {}, Neighbor Entries (both directions): {}",
+            stats.vertices,
+            stats.free_edges,
+            stats.viable_edges,
+            stats.blocked_edges,
+            stats.blocked_vertices,
+            stats.obstacles,
+            stats.cost_regions,
+            stats.soft_obstacles,
+            stats.direction_zones,
+            stats.moving_obstacles,
+            stats.disabled_obstacles,
+            stats.neighbors,
+            stats.neighbor_edges
+        );
+    }
+
+    /// Returns the current set of obstacles.
+    pub fn obstacles(&self) -> &ObstacleSet {
+        &self.obstacles
+    }
+
+    /// Returns the nearest vertex to the given point that is not inside an obstacle.
+    pub fn get_nearest(&self, point: Point<f64>) -> Vertex {
+        self.get_nearest_with_footprint(0, point)
+    }
+
+    /// Returns the nearest vertex to the given point that is free for the given footprint.
+    pub fn get_nearest_with_footprint(&self, footprint: FootprintIndex, point: Point<f64>) -> Vertex {
+        let view = &self.views[footprint];
+        let mut min_distance = f64::MAX;
+        let mut nearest: VertexIndex = 0;
+        for (vid, v) in self.vertices.iter().enumerate() {
+            let distance = v.euclidean_distance(&point);
+            if distance < min_distance && view.is_vertex_free(vid) {
+                min_distance = distance;
+                nearest = vid;
+            }
+        }
+        self.vertex(nearest)
+    }
+
+    /// Returns the vertex with the given index.
+    pub fn vertex(&self, index: VertexIndex) -> Vertex {
+        Vertex {
+            point: self.vertices[index],
+            index,
+        }
+    }
+
+    pub fn is_free(&self, point: &Point<f64>) -> bool {
+        !self.obstacles.contains_with_clearance(point, self.cfg.robot_radius)
+    }
+}
+
+/// Serializes a HashMap in key order, so that equal maps serialize to equal bytes.
+/// Deserializes as a regular map.
+pub(crate) fn serialize_sorted<S, K, V>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    K: Serialize + Ord,
+    V: Serialize,
+{
+    let sorted: BTreeMap<&K, &V> = map.iter().collect();
+    sorted.serialize(serializer)
+}
+
+/// The thread count is a property of the machine and not of the roadmap,
+/// it is stored as 0 so that a loaded roadmap uses all available cores.
+fn serialize_without_threads<S: Serializer>(cfg: &PrmConfig, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut cfg = cfg.clone();
+    cfg.threads = 0;
+    cfg.serialize(serializer)
+}
+
+/// An inconsistency in the internal state of a DPrm, see `DPrm::validate`.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum Inconsistency {
+    /// The id is used by more than one obstacle.
+    DuplicateObstacle(ObstacleId),
+    /// The obstacle has no entry in the blocked edges or contained vertices per obstacle.
+    MissingBlockings(ObstacleId),
+    /// There are blocked edges or contained vertices for an obstacle that does not exist.
+    UnknownObstacle(ObstacleId),
+    /// The obstacle blocks an edge that does not exist.
+    UnknownEdge { obstacle: ObstacleId, edge: EdgeIndex },
+    /// The edge has an endpoint that does not exist.
+    UnknownVertex { edge: EdgeIndex, vertex: VertexIndex },
+    /// The blocking counter of the edge differs from the number of obstacles blocking it.
+    BlockingCount { edge: EdgeIndex, expected: usize, actual: usize },
+    /// The obstacle contains a vertex that does not exist.
+    UnknownContainedVertex { obstacle: ObstacleId, vertex: VertexIndex },
+    /// The blocking counter of the vertex differs from the number of obstacles containing it.
+    VertexBlockingCount { vertex: VertexIndex, expected: usize, actual: usize },
+    /// The id is used by more than one cost region.
+    DuplicateRegion(RegionId),
+    /// The cost region has no entry in the extra costs per region.
+    MissingCosts(RegionId),
+    /// There are extra costs for a cost region that does not exist.
+    UnknownRegion(RegionId),
+    /// The cost region has an extra cost for an edge that does not exist.
+    UnknownCostedEdge { region: RegionId, edge: EdgeIndex },
+    /// The id is used by more than one soft obstacle.
+    DuplicateSoftObstacle(ObstacleId),
+    /// The soft obstacle has no entry in the penalized edges per obstacle.
+    MissingPenalties(ObstacleId),
+    /// There are penalized edges for a soft obstacle that does not exist.
+    UnknownSoftObstacle(ObstacleId),
+    /// The soft obstacle penalizes an edge that does not exist.
+    UnknownPenalizedEdge { obstacle: ObstacleId, edge: EdgeIndex },
+    /// There is an expiry for an obstacle that does not exist.
+    UnknownExpiry(ObstacleId),
+    /// The obstacle is kept out of the views although its layer is enabled.
+    EnabledButParked(ObstacleId),
+    /// The obstacle blocks edges although its layer is disabled.
+    DisabledButActive(ObstacleId),
+    /// There is a layer for an obstacle that does not exist.
+    UnknownLayerObstacle(ObstacleId),
+    /// The id is used by more than one moving obstacle.
+    DuplicateMovingObstacle(ObstacleId),
+    /// The id is used by more than one zone.
+    DuplicateZone(ZoneId),
+    /// The zone has no entry in the blocked directions per zone.
+    MissingZoneBlockings(ZoneId),
+    /// There are blocked directions for a zone that does not exist.
+    UnknownZone(ZoneId),
+    /// The zone blocks an edge that does not exist.
+    UnknownZoneEdge { zone: ZoneId, edge: EdgeIndex },
+    /// The number of zones blocking the direction of the edge differs from its counter.
+    DirectionBlockingCount { edge: EdgeIndex, direction: Direction, expected: usize, actual: usize },
+    /// The extra cost of the edge differs from the sum over the cost regions and soft obstacles.
+    ExtraCost { edge: EdgeIndex, expected: Distance, actual: Distance },
+    /// An inconsistency in the blockings of a footprint other than the first.
+    Footprint { footprint: FootprintIndex, inconsistency: Box<Inconsistency> },
+    /// An edge is missing from the neighbors.
+    MissingNeighbor { from: VertexIndex, to: VertexIndex },
+    /// The neighbors contain an unknown edge.
+    UnexpectedNeighbor { from: VertexIndex, to: VertexIndex },
 }
-const EMPTY: &'static Vec<(VertexIndex, Distance)> = &Vec::new();
 
-impl Neighbors {
-    fn new() -> Neighbors {
-        Neighbors {
-            inner: HashMap::new(),
+/// The inconsistencies found when loading a DPrm with `DPrm::from_file_validated`.
+#[derive(Debug)]
+pub struct ValidationError(pub Vec<Inconsistency>);
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DPrm has {} inconsistencies", self.0.len())?;
+        for inconsistency in self.0.iter() {
+            write!(f, "\n  {:?}", inconsistency)?;
         }
+        Ok(())
     }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// The obstacles removed by `DPrm::advance_clock`, in order of expiry,
+/// and the edges of the first footprint they freed, in ascending order.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Expired {
+    pub obstacles: Vec<Obstacle>,
+    pub freed_edges: Vec<EdgeIndex>,
+}
+
+/// Counts describing the state of a DPrm, see `DPrm::stats`.
+/// Edges are undirected and counted once.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DPrmStats {
+    pub vertices: usize,
+    pub free_edges: usize,
+    pub viable_edges: usize,
+    pub blocked_edges: usize,
+    /// Vertices inside an obstacle.
+    pub blocked_vertices: usize,
+    pub obstacles: usize,
+    pub cost_regions: usize,
+    pub soft_obstacles: usize,
+    pub direction_zones: usize,
+    pub moving_obstacles: usize,
+    /// Obstacles of disabled layers, not counted in `obstacles`.
+    pub disabled_obstacles: usize,
+    /// Vertices with at least one free edge.
+    pub neighbors: usize,
+    /// Free edges seen from both endpoints, twice `free_edges` unless one-way rules forbid a direction.
+    pub neighbor_edges: usize,
+}
 
-    // Point to an empty vector
-    fn get(&self, v: &VertexIndex) -> &Vec<(VertexIndex, Distance)> {
-        if let Some(vec) = self.inner.get(v) {
-            vec
-        } else { EMPTY }
+/// Bytes used by the dense storage of a DPrm, see `DPrm::memory`.
+/// Obstacles and their blocked edges are not included.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DPrmMemory {
+    pub edges: usize,
+    /// Coordinates, blocking counters and neighbor offsets
+    pub vertex_bytes: usize,
+    pub edge_bytes: usize,
+    pub counter_bytes: usize,
+    pub neighbor_bytes: usize,
+}
+
+impl DPrmMemory {
+    pub fn total_bytes(&self) -> usize {
+        self.vertex_bytes + self.edge_bytes + self.counter_bytes + self.neighbor_bytes
     }
 
-    fn add(&mut self, e: &Edge) {
-        self.inner.entry(e.points.0).or_insert(Vec::new()).push((e.points.1, e.length.round() as Distance));
-        self.inner.entry(e.points.1).or_insert(Vec::new()).push((e.points.0, e.length.round() as Distance));
+    /// Bytes of edges, counters and neighbors per edge, comparable to `BYTES_PER_EDGE`.
+    pub fn bytes_per_edge(&self) -> f64 {
+        (self.edge_bytes + self.counter_bytes + self.neighbor_bytes) as f64 / self.edges.max(1) as f64
     }
+}
 
-    fn remove(&mut self, e: &Edge) {
-        self.inner.entry(e.points.0).or_insert(Vec::new()).retain(|(v, _)| *v != e.points.1);
-        self.inner.entry(e.points.1).or_insert(Vec::new()).retain(|(v, _)| *v != e.points.0);
+/// An edge stored as the indices of its endpoints and its length, the line is derived from the vertices.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub(crate) struct CompactEdge {
+    pub(crate) from: u32,
+    pub(crate) to: u32,
+    pub(crate) length: f32,
+}
+
+impl CompactEdge {
+    pub(crate) fn points(&self) -> (VertexIndex, VertexIndex) {
+        (self.from as VertexIndex, self.to as VertexIndex)
+    }
+
+    pub(crate) fn line(&self, vertices: &[Point<f64>]) -> Line<f64> {
+        Line::new(vertices[self.from as usize], vertices[self.to as usize])
+    }
+
+    pub(crate) fn distance(&self) -> Distance {
+        self.length.round() as Distance
+    }
+}
+
+/// The edges leaving each vertex in compressed sparse row form.
+/// The entries of vertex v are `entries[offsets[v]..offsets[v + 1]]`, as (to, edge) pairs.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub(crate) struct Adjacency {
+    offsets: Vec<usize>,
+    entries: Vec<(u32, u32)>,
+}
+
+impl Adjacency {
+    /// Each edge is stored once, so it is added in both directions.
+    pub(crate) fn new(num_vertices: usize, edges: &[CompactEdge]) -> Adjacency {
+        let mut offsets = vec![0; num_vertices + 1];
+        for e in edges {
+            offsets[e.from as usize + 1] += 1;
+            offsets[e.to as usize + 1] += 1;
+        }
+        for v in 0..num_vertices {
+            offsets[v + 1] += offsets[v];
+        }
+        let mut next = offsets.clone();
+        let mut entries = vec![(0, 0); 2 * edges.len()];
+        for (i, e) in edges.iter().enumerate() {
+            for (from, to) in [(e.from, e.to), (e.to, e.from)] {
+                entries[next[from as usize]] = (to, i as u32);
+                next[from as usize] += 1;
+            }
+        }
+        Adjacency { offsets, entries }
+    }
+
+    // Unknown vertices have no neighbors
+    pub(crate) fn get(&self, v: VertexIndex) -> &[(u32, u32)] {
+        if v + 1 < self.offsets.len() {
+            &self.entries[self.offsets[v]..self.offsets[v + 1]]
+        } else {
+            &[]
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use geo::Rect;
+    use proptest::prelude::{prop_assert_eq, prop_oneof, proptest, ProptestConfig, TestCaseError};
+    use proptest::{collection::vec, strategy::Strategy};
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        Insert(Rect<f64>),
+        /// Removes the obstacle at this position, modulo the number of obstacles.
+        Remove(usize),
+        InsertRegion(Rect<f64>, f64),
+        /// Removes the cost region at this position, modulo the number of cost regions.
+        RemoveRegion(usize),
+        InsertSoft(Rect<f64>, Distance),
+        /// Removes the soft obstacle at this position, modulo the number of soft obstacles.
+        RemoveSoft(usize),
+        /// Inserts a zone allowing headings from the first to the second angle.
+        InsertZone(Rect<f64>, f64, f64),
+        /// Removes the zone at this position, modulo the number of zones.
+        RemoveZone(usize),
+    }
+
+    fn cfg() -> PrmConfig {
+        PrmConfig::new(150, 100, 100, [7u8; 32], 2)
+    }
+
+    fn rect() -> impl Strategy<Value = Rect<f64>> {
+        (0.0..100.0, 0.0..100.0, 1.0..30.0, 1.0..30.0)
+            .prop_map(|(x, y, w, h): (f64, f64, f64, f64)| Rect::new((x, y), (x + w, y + h)))
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            rect().prop_map(Op::Insert),
+            (0..usize::MAX).prop_map(Op::Remove),
+            (rect(), 1.0..5.0).prop_map(|(rect, multiplier)| Op::InsertRegion(rect, multiplier)),
+            (0..usize::MAX).prop_map(Op::RemoveRegion),
+            (rect(), 0..50usize).prop_map(|(rect, penalty)| Op::InsertSoft(rect, penalty)),
+            (0..usize::MAX).prop_map(Op::RemoveSoft),
+            (rect(), -4.0..4.0, -4.0..4.0).prop_map(|(rect, min, max)| Op::InsertZone(rect, min, max)),
+            (0..usize::MAX).prop_map(Op::RemoveZone),
+        ]
+    }
+
+    fn sorted<T: Ord + Clone>(items: &[T]) -> Vec<T> {
+        let mut items = items.to_vec();
+        items.sort();
+        items
+    }
+
+    /// Checks that `updated` has the same derived state as `fresh`, built from scratch with the same obstacles,
+    /// cost regions, soft obstacles and zones.
+    pub(crate) fn assert_equivalent(updated: &DPrm, fresh: &DPrm) -> Result<(), TestCaseError> {
+        prop_assert_eq!(updated.validate(), Vec::new());
+        prop_assert_eq!(&updated.extra_cost_per_edge, &fresh.extra_cost_per_edge);
+        prop_assert_eq!(&updated.traffic.blockings_forward, &fresh.traffic.blockings_forward);
+        prop_assert_eq!(&updated.traffic.blockings_backward, &fresh.traffic.blockings_backward);
+        prop_assert_eq!(sorted(&updated.get_all_free_edges()), sorted(&fresh.get_all_free_edges()));
+        prop_assert_eq!(updated.footprints(), fresh.footprints());
+        for (footprint, (updated_view, fresh_view)) in updated.views.iter().zip(fresh.views.iter()).enumerate() {
+            prop_assert_eq!(&updated_view.blockings_per_edge, &fresh_view.blockings_per_edge);
+            prop_assert_eq!(&updated_view.blockings_per_vertex, &fresh_view.blockings_per_vertex);
+            for o in fresh.obstacles.obstacles.iter() {
+                prop_assert_eq!(
+                    sorted(&updated_view.blocked_per_obstacle[&o.id()]),
+                    sorted(&fresh_view.blocked_per_obstacle[&o.id()])
+                );
+                prop_assert_eq!(&updated_view.contained_per_obstacle[&o.id()], &fresh_view.contained_per_obstacle[&o.id()]);
+            }
+            for v in 0..fresh.vertices.len() {
+                prop_assert_eq!(
+                    sorted(&updated.successors(updated_view, &v)),
+                    sorted(&fresh.successors(fresh_view, &v)),
+                    "neighbors of vertex {}", v
+                );
+            }
+            let n = fresh.vertices.len();
+            for (start, end) in [(0, n - 1), (1, n / 2), (n / 3, 2 * n / 3)] {
+                prop_assert_eq!(
+                    updated.run_astar_with_footprint(footprint, &start, &end).map(|p| p.length),
+                    fresh.run_astar_with_footprint(footprint, &start, &end).map(|p| p.length)
+                );
+            }
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        /// Any sequence of insertions and removals gives the same roadmap as building it
+        /// from scratch with the final set of obstacles, cost regions, soft obstacles and zones.
+        #[test]
+        fn updates_match_recomputation(initial in vec(rect(), 0..4), ops in vec(op(), 1..12)) {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            let initial = ObstacleSet {
+                obstacles: initial.into_iter().enumerate().map(|(i, rect)| Obstacle { rect, id: i as ObstacleId }).collect(),
+            };
+            let mut next_id = initial.obstacles.len() as ObstacleId;
+            let mut updated = runtime.block_on(DPrm::from_cfg(cfg(), initial));
+            runtime.block_on(updated.add_footprint(2.0));
+            for op in ops {
+                match op {
+                    Op::Insert(rect) => {
+                        let obstacle = Obstacle { rect, id: next_id };
+                        next_id += 1;
+                        let blockings = runtime.block_on(updated.find_blocked_by_obstacle(obstacle));
+                        updated.insert_blocked_by_obstacle(obstacle, blockings);
+                    }
+                    Op::Remove(i) => {
+                        let obstacles = &updated.obstacles.obstacles;
+                        if !obstacles.is_empty() {
+                            let oid = obstacles[i % obstacles.len()].id();
+                            updated.remove_obstacle(oid);
+                        }
+                    }
+                    Op::InsertRegion(rect, multiplier) => {
+                        let region = CostRegion::new(rect.to_polygon(), multiplier, next_id);
+                        next_id += 1;
+                        let costs = runtime.block_on(updated.find_costs_of_region(&region));
+                        updated.insert_cost_region(region, costs);
+                    }
+                    Op::RemoveRegion(i) => {
+                        let regions = updated.cost_regions();
+                        if !regions.is_empty() {
+                            let id = regions[i % regions.len()].id;
+                            updated.remove_cost_region(id);
+                        }
+                    }
+                    Op::InsertSoft(rect, penalty) => {
+                        let soft = SoftObstacle::new(Obstacle { rect, id: next_id }, penalty);
+                        next_id += 1;
+                        let penalized = runtime.block_on(updated.find_penalized_by_soft_obstacle(&soft));
+                        updated.insert_soft_obstacle(soft, penalized);
+                    }
+                    Op::RemoveSoft(i) => {
+                        let softs = updated.soft_obstacles();
+                        if !softs.is_empty() {
+                            let id = softs[i % softs.len()].id();
+                            updated.remove_soft_obstacle(id);
+                        }
+                    }
+                    Op::InsertZone(rect, min, max) => {
+                        let zone = DirectionZone::new(rect.to_polygon(), min, max, next_id);
+                        next_id += 1;
+                        let blocked = runtime.block_on(updated.find_blocked_by_zone(&zone));
+                        updated.insert_direction_zone(zone, blocked);
+                    }
+                    Op::RemoveZone(i) => {
+                        let zones = updated.direction_zones();
+                        if !zones.is_empty() {
+                            let id = zones[i % zones.len()].id;
+                            updated.remove_direction_zone(id);
+                        }
+                    }
+                }
+            }
+            let mut fresh = runtime.block_on(DPrm::from_cfg(cfg(), updated.obstacles.clone()));
+            runtime.block_on(fresh.add_footprint(2.0));
+            for region in updated.cost_regions() {
+                let costs = runtime.block_on(fresh.find_costs_of_region(region));
+                fresh.insert_cost_region(region.clone(), costs);
+            }
+            for soft in updated.soft_obstacles() {
+                let penalized = runtime.block_on(fresh.find_penalized_by_soft_obstacle(soft));
+                fresh.insert_soft_obstacle(*soft, penalized);
+            }
+            for zone in updated.direction_zones() {
+                let blocked = runtime.block_on(fresh.find_blocked_by_zone(zone));
+                fresh.insert_direction_zone(zone.clone(), blocked);
+            }
+            assert_equivalent(&updated, &fresh)?;
+        }
+    }
+
+    #[tokio::test]
+    async fn validate_reports_and_rebuild_repairs() {
+        let obstacle = Obstacle { rect: Rect::new((40.0, 40.0), (60.0, 60.0)), id: 1 };
+        let mut dprm = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: vec![obstacle] }).await;
+        assert_eq!(dprm.validate(), Vec::new());
+        let blocked = dprm.views[0].blocked_per_obstacle[&1][0];
+
+        // Corrupt every piece of redundant state
+        dprm.obstacles.add(obstacle);
+        dprm.views[0].blocked_per_obstacle.insert(2, Arc::new(vec![blocked]));
+        dprm.views[0].blockings_per_edge[blocked] = 5;
+        let mut edges = dprm.edges.to_vec();
+        edges.remove(blocked);
+        dprm.neighbors = Arc::new(Adjacency::new(dprm.vertices.len(), &edges));
+
+        let inconsistencies = dprm.validate();
+        assert!(inconsistencies.contains(&Inconsistency::DuplicateObstacle(1)));
+        assert!(inconsistencies.contains(&Inconsistency::UnknownObstacle(2)));
+        assert!(inconsistencies.contains(&Inconsistency::BlockingCount { edge: blocked, expected: 2, actual: 5 }));
+        let (from, to) = dprm.edges[blocked].points();
+        assert!(inconsistencies.contains(&Inconsistency::MissingNeighbor { from, to }));
+
+        dprm.rebuild_derived_state().await;
+        assert_eq!(dprm.validate(), Vec::new());
+        let fresh = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: vec![obstacle] }).await;
+        assert_equivalent(&dprm, &fresh).unwrap();
+    }
+
+    #[tokio::test]
+    async fn removing_an_unblocked_edge_does_not_underflow() {
+        let obstacle = Obstacle { rect: Rect::new((40.0, 40.0), (60.0, 60.0)), id: 1 };
+        let mut dprm = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: vec![obstacle] }).await;
+        let blocked = dprm.views[0].blocked_per_obstacle[&1][0];
+        dprm.views[0].blockings_per_edge[blocked] = 0;
+        dprm.remove_obstacle(1);
+        assert_eq!(dprm.views[0].blockings_per_edge[blocked], 0);
+        // Inserting an obstacle with an id that is in use is refused
+        let mut dprm = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: vec![obstacle] }).await;
+        let blockings = Blockings { edges: vec![vec![blocked]], vertices: vec![Vec::new()] };
+        assert!(dprm.insert_blocked_by_obstacle(obstacle, blockings).is_empty());
+        assert_eq!(dprm.validate(), Vec::new());
+    }
+
+    #[tokio::test]
+    async fn construction_does_not_depend_on_threads() {
+        let obstacles = ObstacleSet {
+            obstacles: vec![Obstacle { rect: Rect::new((40.0, 40.0), (60.0, 60.0)), id: 1 }],
+        };
+        let mut serialized = Vec::new();
+        for threads in [1, 3, 8, 500] {
+            let mut cfg = cfg();
+            cfg.threads = threads;
+            let dprm = DPrm::from_cfg(cfg, obstacles.clone()).await;
+            serialized.push(bincode::serialize(&dprm).unwrap());
+        }
+        assert!(serialized.windows(2).all(|w| w[0] == w[1]));
+        let dprm: DPrm = bincode::deserialize(&serialized[0]).unwrap();
+        assert_eq!(dprm.validate(), Vec::new());
+    }
+
+    #[tokio::test]
+    async fn dense_storage_uses_documented_bytes_per_edge() {
+        let dprm = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: Vec::new() }).await;
+        let memory = dprm.memory();
+        assert_eq!(memory.edges, dprm.edges.len());
+        assert_eq!(memory.bytes_per_edge(), BYTES_PER_EDGE as f64);
+        assert_eq!(BYTES_PER_EDGE, 30);
+    }
+
+    #[tokio::test]
+    async fn edges_are_stored_once_without_self_loops() {
+        let dprm = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: Vec::new() }).await;
+        let mut pairs = HashSet::new();
+        for edge in dprm.edges.iter() {
+            let (from, to) = edge.points();
+            assert!(from < to, "edge ({}, {})", from, to);
+            assert!(pairs.insert((from, to)));
+        }
+        let stats = dprm.stats();
+        assert_eq!(stats.free_edges, dprm.edges.len());
+        assert_eq!(stats.neighbor_edges, 2 * stats.free_edges);
+    }
+
+    #[tokio::test]
+    async fn contained_vertices_are_blocked_until_removed() {
+        let mut dprm = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: Vec::new() }).await;
+        let obstacle = Obstacle { rect: Rect::new((30.0, 30.0), (70.0, 70.0)), id: 1 };
+        let inside = dprm.find_contained_by_obstacle(&obstacle, 0.0)[0];
+        let outside = (0..dprm.vertices.len()).find(|v| !obstacle.contains(&dprm.vertices[*v])).unwrap();
+        assert!(dprm.run_astar(&inside, &outside).is_some());
+
+        let blockings = dprm.find_blocked_by_obstacle(obstacle).await;
+        dprm.insert_blocked_by_obstacle(obstacle, blockings);
+        assert!(!dprm.is_vertex_free(inside));
+        assert_ne!(dprm.get_nearest(dprm.vertices[inside]).index, inside);
+        assert!(dprm.run_astar(&inside, &outside).is_none());
+        assert!(dprm.run_astar(&inside, &inside).is_none());
+        assert_eq!(dprm.stats().blocked_vertices, dprm.views[0].contained_per_obstacle[&1].len());
+        assert_eq!(dprm.validate(), Vec::new());
+
+        dprm.remove_obstacle(1);
+        assert!(dprm.is_vertex_free(inside));
+        assert_eq!(dprm.get_nearest(dprm.vertices[inside]).index, inside);
+        assert!(dprm.run_astar(&inside, &outside).is_some());
+    }
+
+    #[tokio::test]
+    async fn footprints_keep_their_clearance() {
+        let mut dprm = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: Vec::new() }).await;
+        let wide = dprm.add_footprint(3.0).await;
+        let obstacle = Obstacle { rect: Rect::new((40.0, 20.0), (60.0, 80.0)), id: 1 };
+        let blockings = dprm.find_blocked_by_obstacle(obstacle).await;
+        dprm.insert_blocked_by_obstacle(obstacle, blockings);
+        assert_eq!(dprm.validate(), Vec::new());
+
+        // The wide footprint blocks everything the point blocks and more
+        let (point, wide_view) = (&dprm.views[0], &dprm.views[wide]);
+        assert!(point.blocked_per_obstacle[&1].iter().all(|e| wide_view.blocked_per_obstacle[&1].contains(e)));
+        assert!(wide_view.blocked_per_obstacle[&1].len() > point.blocked_per_obstacle[&1].len());
+
+        // A roadmap built for the wide robot has the same blockings as the shared view
+        let mut wide_cfg = cfg();
+        wide_cfg.robot_radius = 3.0;
+        let fresh = DPrm::from_cfg(wide_cfg, dprm.obstacles.clone()).await;
+        assert_eq!(fresh.views[0].blockings_per_edge, wide_view.blockings_per_edge);
+        assert_eq!(fresh.views[0].blockings_per_vertex, wide_view.blockings_per_vertex);
+
+        let start = dprm.get_nearest_with_footprint(wide, Point::new(10.0, 50.0));
+        let end = dprm.get_nearest_with_footprint(wide, Point::new(90.0, 50.0));
+        let path = dprm.run_astar_with_footprint(wide, &start.index, &end.index).unwrap();
+        for pair in path.vertices.windows(2) {
+            let line = Line::new(pair[0].point, pair[1].point);
+            assert!(!obstacle.intersects_with_clearance(&line, 3.0));
+        }
+
+        dprm.views[wide].blockings_per_vertex[start.index] = 1;
+        assert!(dprm.validate().contains(&Inconsistency::Footprint {
+            footprint: wide,
+            inconsistency: Box::new(Inconsistency::VertexBlockingCount { vertex: start.index, expected: 0, actual: 1 }),
+        }));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn cost_regions_weight_paths_until_removed() {
+        let mut dprm = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: Vec::new() }).await;
+        let n = dprm.vertices.len();
+        let base = dprm.run_astar(&0, &(n - 1)).unwrap().length;
+        let id: RegionId = 0;
+        let everywhere = CostRegion::new(Rect::new((0.0, 0.0), (100.0, 100.0)).to_polygon(), 3.0, id);
+        let costs = dprm.find_costs_of_region(&everywhere).await;
+        assert!(!dprm.insert_cost_region(everywhere.clone(), costs.clone()).is_empty());
+        assert_eq!(dprm.validate(), Vec::new());
+        let weighted = dprm.run_astar(&0, &(n - 1)).unwrap().length;
+        assert!(weighted > 2 * base, "{} vs. {}", weighted, base);
+
+        // Duplicate ids and multipliers below 1 are refused
+        assert!(dprm.insert_cost_region(everywhere.clone(), costs.clone()).is_empty());
+        let cheap = CostRegion::new(everywhere.polygon.clone(), 0.5, 1);
+        assert!(dprm.insert_cost_region(cheap, costs).is_empty());
+        assert_eq!(dprm.cost_regions().len(), 1);
+
+        // A region whose extra costs do not fit in 32 bits is refused before any edge is changed
+        let steep = CostRegion::new(everywhere.polygon.clone(), 1e10, 2);
+        assert!(steep.is_valid());
+        let steep_costs = dprm.find_costs_of_region(&steep).await;
+        let before = dprm.extra_cost_per_edge.clone();
+        assert!(dprm.insert_cost_region(steep, steep_costs).is_empty());
+        assert_eq!(dprm.extra_cost_per_edge, before);
+        assert_eq!(dprm.cost_regions().len(), 1);
+
+        // Persisted with the roadmap
+        let restored: DPrm = bincode::deserialize(&bincode::serialize(&dprm).unwrap()).unwrap();
+        assert_eq!(restored.validate(), Vec::new());
+        assert_eq!(restored.run_astar(&0, &(n - 1)).unwrap().length, weighted);
+
+        dprm.remove_cost_region(id);
+        assert_eq!(dprm.validate(), Vec::new());
+        assert_eq!(dprm.run_astar(&0, &(n - 1)).unwrap().length, base);
+        assert_eq!(dprm.stats().cost_regions, 0);
+    }
+
+    #[tokio::test]
+    async fn soft_obstacles_penalize_without_blocking() {
+        let mut dprm = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: Vec::new() }).await;
+        let obstacle = Obstacle { rect: Rect::new((30.0, 30.0), (70.0, 70.0)), id: 1 };
+        let inside = dprm.find_contained_by_obstacle(&obstacle, 0.0)[0];
+        let outside = (0..dprm.vertices.len()).find(|v| !obstacle.contains(&dprm.vertices[*v])).unwrap();
+        let base = dprm.run_astar(&inside, &outside).unwrap().length;
+
+        let soft = SoftObstacle::new(obstacle, 1000);
+        let penalized = dprm.find_penalized_by_soft_obstacle(&soft).await;
+        assert_eq!(dprm.insert_soft_obstacle(soft, penalized.clone()), penalized);
+        assert!(dprm.insert_soft_obstacle(soft, penalized.clone()).is_empty());
+        assert_eq!(dprm.validate(), Vec::new());
+        assert!(dprm.is_vertex_free(inside));
+        assert!(dprm.run_astar(&inside, &outside).unwrap().length >= base + 1000);
+
+        // Penalties that push an edge beyond 32 bits are refused before any edge is changed,
+        // and large ones that fit are subtracted exactly
+        let before = dprm.extra_cost_per_edge.clone();
+        let heavy = SoftObstacle::new(Obstacle { id: 2, ..obstacle }, 5_000_000_000);
+        assert!(dprm.insert_soft_obstacle(heavy, penalized.clone()).is_empty());
+        let heavy = SoftObstacle::new(Obstacle { id: 2, ..obstacle }, u32::MAX as Distance - 1000);
+        assert_eq!(dprm.insert_soft_obstacle(heavy, penalized.clone()), penalized);
+        let heavier = SoftObstacle::new(Obstacle { id: 3, ..obstacle }, 1);
+        assert!(dprm.insert_soft_obstacle(heavier, penalized.clone()).is_empty());
+        dprm.remove_soft_obstacle(2);
+        assert_eq!(dprm.extra_cost_per_edge, before);
+        assert_eq!(dprm.validate(), Vec::new());
+
+        dprm.remove_soft_obstacle(1);
+        assert_eq!(dprm.validate(), Vec::new());
+        assert_eq!(dprm.run_astar(&inside, &outside).unwrap().length, base);
+        assert!(dprm.extra_cost_per_edge.is_empty());
+    }
+
+    #[tokio::test]
+    async fn one_way_zones_block_one_direction_until_toggled() {
+        let mut dprm = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: Vec::new() }).await;
+        let by_x = |v: &VertexIndex| (dprm.vertices[*v].x() * 1000.0) as i64;
+        let west = (0..dprm.vertices.len()).min_by_key(by_x).unwrap();
+        let east = (0..dprm.vertices.len()).max_by_key(by_x).unwrap();
+        assert!(dprm.run_astar(&east, &west).is_some());
+
+        // Only eastbound traffic
+        let half_pi = std::f64::consts::FRAC_PI_2;
+        let zone = DirectionZone::new(Rect::new((0.0, 0.0), (100.0, 100.0)).to_polygon(), -half_pi, half_pi, 1);
+        let blocked = dprm.find_blocked_by_zone(&zone).await;
+        assert!(!dprm.insert_direction_zone(zone.clone(), blocked.clone()).is_empty());
+        assert!(dprm.insert_direction_zone(zone, blocked).is_empty());
+        assert_eq!(dprm.validate(), Vec::new());
+        assert!(dprm.run_astar(&west, &east).is_some());
+        assert!(dprm.run_astar(&east, &west).is_none());
+
+        dprm.set_traffic_rules_enabled(false);
+        assert!(dprm.run_astar(&east, &west).is_some());
+        dprm.set_traffic_rules_enabled(true);
+        assert!(dprm.run_astar(&east, &west).is_none());
+
+        dprm.remove_direction_zone(1);
+        assert_eq!(dprm.validate(), Vec::new());
+        assert!(dprm.run_astar(&east, &west).is_some());
+        assert_eq!(dprm.stats().neighbor_edges, 2 * dprm.stats().free_edges);
+
+        // A full turn allows every heading, while the same bounds taken modulo a turn would allow only one
+        let tau = std::f64::consts::TAU;
+        let full_turn = DirectionZone::new(Rect::new((0.0, 0.0), (100.0, 100.0)).to_polygon(), -half_pi, tau - half_pi, 2);
+        assert!([0.0, half_pi, -half_pi, 3.0, -3.0].iter().all(|h| full_turn.allows(*h)));
+        assert!(dprm.find_blocked_by_zone(&full_turn).await.is_empty());
+        let wrapped = DirectionZone::new(full_turn.polygon.clone(), half_pi, -half_pi, 3);
+        assert!(wrapped.allows(std::f64::consts::PI) && !wrapped.allows(0.0));
+    }
+
+    #[tokio::test]
+    async fn timed_paths_wait_for_moving_obstacles() {
+        let mut dprm = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: Vec::new() }).await;
+        let (start, end) = (0, dprm.vertices.len() - 1);
+        let query = TimedQuery::new(2.0, 50.0, 0.1, 100.0);
+        let free = dprm.run_timed_astar(&start, &end, &query).unwrap();
+        assert_eq!(free.vertices[0].time, 2.0);
+        assert!(free.arrival < 12.0);
+
+        // Parked on the end vertex until t = 12, then gone
+        let parked = dprm.vertices[end];
+        dprm.add_moving_obstacle(MovingObstacle::new(4.0, 4.0, vec![(0.0, parked), (12.0, parked)], 1));
+        dprm.add_moving_obstacle(MovingObstacle::new(4.0, 4.0, vec![(0.0, parked)], 1));
+        assert_eq!(dprm.moving_obstacles().len(), 1);
+        let timed = dprm.run_timed_astar(&start, &end, &query).unwrap();
+        assert!(timed.arrival > 12.0, "{}", timed.arrival);
+        assert!(timed.vertices.windows(2).all(|w| w[0].time <= w[1].time));
+        assert_eq!(timed.vertices.last().unwrap().vertex.index, end);
+        assert!(dprm.run_timed_astar(&start, &end, &TimedQuery::new(2.0, 50.0, 0.1, 5.0)).is_none());
+        // Crawling so slowly that a single edge takes more steps than fit in a u32
+        assert!(dprm.run_timed_astar(&start, &end, &TimedQuery::new(2.0, 1e-300, 1.0, 10.0)).is_none());
+        let stalled = TimedQuery { time_step: 0.0, ..query.clone() };
+        assert!(!stalled.is_valid());
+        assert!(dprm.run_timed_astar(&start, &end, &stalled).is_none());
+        assert!(std::panic::catch_unwind(|| TimedQuery::new(2.0, f64::NAN, 0.1, 100.0)).is_err());
+        assert!(std::panic::catch_unwind(|| TimedQuery::new(2.0, 50.0, 0.1, -1.0)).is_err());
+
+        dprm.remove_moving_obstacle(1);
+        assert_eq!(dprm.run_timed_astar(&start, &end, &query).unwrap().arrival, free.arrival);
+    }
+
+    #[tokio::test]
+    async fn advancing_the_clock_removes_expired_obstacles() {
+        let permanent = Obstacle { rect: Rect::new((10.0, 10.0), (20.0, 20.0)), id: 1 };
+        let mut dprm = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: vec![permanent] }).await;
+        let soon = Obstacle { rect: Rect::new((40.0, 40.0), (60.0, 60.0)), id: 2 };
+        let later = Obstacle { rect: Rect::new((70.0, 10.0), (90.0, 30.0)), id: 3 };
+        for (obstacle, expires_at) in [(later, 10.0), (soon, 5.0)] {
+            let blockings = dprm.find_blocked_by_obstacle(obstacle).await;
+            dprm.insert_expiring_obstacle(obstacle, blockings, expires_at);
+        }
+        let blocked_by_soon = sorted(&dprm.views[0].blocked_per_obstacle[&2]);
+        assert!(dprm.advance_clock(4.0).obstacles.is_empty());
+
+        let expired = dprm.advance_clock(7.0);
+        assert_eq!(expired.obstacles, vec![soon]);
+        assert_eq!(expired.freed_edges, blocked_by_soon);
+        assert!(!dprm.contains_obstacle(2));
+        assert_eq!(dprm.validate(), Vec::new());
+
+        // The clock does not move back, and expiry can be changed or cleared
+        assert!(dprm.advance_clock(3.0).obstacles.is_empty());
+        assert!(dprm.advance_clock(f64::NAN).obstacles.is_empty());
+        assert!(dprm.advance_clock(f64::INFINITY).obstacles.is_empty());
+        assert_eq!(dprm.clock(), 7.0);
+        dprm.set_obstacle_expiry(1, Some(8.0));
+        dprm.set_obstacle_expiry(3, None);
+        let restored: DPrm = bincode::deserialize(&bincode::serialize(&dprm).unwrap()).unwrap();
+        assert_eq!(restored.obstacle_expiry(1), Some(8.0));
+        let expired = dprm.advance_clock(100.0);
+        assert_eq!(expired.obstacles, vec![permanent]);
+        assert!(dprm.contains_obstacle(3));
+        assert_eq!(dprm.validate(), Vec::new());
+    }
+
+    #[tokio::test]
+    async fn layers_toggle_their_obstacles_together() {
+        let wall = Obstacle { rect: Rect::new((10.0, 10.0), (20.0, 20.0)), id: 1 };
+        let doors = [
+            Obstacle { rect: Rect::new((40.0, 0.0), (45.0, 50.0)), id: 2 },
+            Obstacle { rect: Rect::new((40.0, 50.0), (45.0, 100.0)), id: 3 },
+        ];
+        let mut dprm = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: vec![wall] }).await;
+        let open = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: vec![wall] }).await;
+        let closed = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: vec![wall, doors[0], doors[1]] }).await;
+        for door in doors {
+            let blockings = dprm.find_blocked_by_obstacle(door).await;
+            dprm.insert_obstacle_in_layer(door, blockings, 7);
+        }
+        assert_equivalent(&dprm, &closed).unwrap();
+
+        let freed = dprm.disable_layer(7);
+        assert_eq!(freed, sorted(&closed.get_all_blocked().into_iter().filter(|e| open.is_edge_free(*e)).collect::<Vec<_>>()));
+        assert!(!dprm.is_layer_enabled(7));
+        assert_eq!(dprm.disabled_obstacles(), doors.to_vec());
+        assert_eq!(dprm.validate(), Vec::new());
+        assert_eq!(sorted(&dprm.get_all_free_edges()), sorted(&open.get_all_free_edges()));
+
+        // Obstacles of a disabled layer block nothing, also for new footprints, and are saved with the roadmap
+        let extra = Obstacle { rect: Rect::new((60.0, 60.0), (70.0, 70.0)), id: 4 };
+        let blockings = dprm.find_blocked_by_obstacle(extra).await;
+        assert!(dprm.insert_obstacle_in_layer(extra, blockings, 7).is_empty());
+        dprm.add_footprint(2.0).await;
+        let mut dprm: DPrm = bincode::deserialize(&bincode::serialize(&dprm).unwrap()).unwrap();
+        assert_eq!(dprm.stats().disabled_obstacles, 3);
+        assert_eq!(dprm.validate(), Vec::new());
+
+        assert!(!dprm.enable_layer(7).is_empty());
+        assert_eq!(dprm.validate(), Vec::new());
+        let mut fresh = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: vec![wall, doors[0], doors[1], extra] }).await;
+        fresh.add_footprint(2.0).await;
+        assert_equivalent(&dprm, &fresh).unwrap();
+        dprm.disable_layer(7);
+        dprm.rebuild_derived_state().await;
+        assert_eq!(dprm.validate(), Vec::new());
+        assert!(dprm.remove_obstacle(4).is_empty());
+        assert_eq!(dprm.obstacle_layer(4), None);
+    }
+
+    #[tokio::test]
+    async fn per_query_filters_leave_the_roadmap_alone() {
+        let wall = Obstacle { rect: Rect::new((10.0, 10.0), (20.0, 20.0)), id: 1 };
+        let dprm = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: vec![wall] }).await;
+        let before = bincode::serialize(&dprm).unwrap();
+        let n = dprm.vertices.len();
+        let keep_outs = [
+            Obstacle { rect: Rect::new((30.0, 0.0), (40.0, 80.0)), id: 2 },
+            Obstacle { rect: Rect::new((60.0, 20.0), (70.0, 100.0)), id: 3 },
+        ];
+        let queries = [(0, n - 1), (1, n / 2), (n / 3, 2 * n / 3)];
+
+        // Each keep-out zone gives the same lengths as inserting it
+        let mut expected = Vec::new();
+        for keep_out in keep_outs {
+            let mut inserted = dprm.clone();
+            let blockings = inserted.find_blocked_by_obstacle(keep_out).await;
+            inserted.insert_blocked_by_obstacle(keep_out, blockings);
+            expected.push(queries.map(|(start, end)| inserted.run_astar(&start, &end).map(|p| p.length)));
+        }
+        let results: Vec<_> = std::thread::scope(|scope| {
+            let handles: Vec<_> = keep_outs
+                .iter()
+                .map(|keep_out| {
+                    let dprm = &dprm;
+                    scope.spawn(move || {
+                        queries.map(|(start, end)| dprm.run_astar_avoiding(&start, &end, &[*keep_out]).map(|p| p.length))
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(results, expected);
+
+        // Predicates that allow everything change nothing
+        for (start, end) in queries {
+            assert_eq!(
+                dprm.run_astar_filtered(&start, &end, |_| true, |_, _| true).map(|p| p.length),
+                dprm.run_astar(&start, &end).map(|p| p.length)
+            );
+        }
+        let path = dprm.run_astar(&0, &(n - 1)).unwrap();
+        let middle = path.vertices[path.vertices.len() / 2].index;
+        if middle != 0 && middle != n - 1 {
+            let detour = dprm.run_astar_filtered(&0, &(n - 1), |v| v.index != middle, |_, _| true);
+            assert!(detour.is_none_or(|p| p.vertices.iter().all(|v| v.index != middle)));
+        }
+        assert_eq!(bincode::serialize(&dprm).unwrap(), before);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::prelude::*;
+use geo::{ConvexHull, EuclideanDistance, MultiPoint, Point, Polygon};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Vertices reachable from, or able to reach, one end of a query, see `DPrm::explain_path`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Region {
+    /// In ascending order.
+    pub vertices: Vec<VertexIndex>,
+    /// Convex hull of the vertices.
+    pub hull: Polygon<f64>,
+}
+
+impl Region {
+    pub(crate) fn new(vertices: Vec<VertexIndex>, points: &[Point<f64>]) -> Region {
+        let hull = MultiPoint::new(vertices.iter().map(|v| points[*v]).collect()).convex_hull();
+        Region { vertices, hull }
+    }
+
+    pub fn contains(&self, vertex: VertexIndex) -> bool {
+        self.vertices.binary_search(&vertex).is_ok()
+    }
+}
+
+/// An edge leaving the start region, with everything that keeps the search from crossing it.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct FrontierEdge {
+    pub edge: EdgeIndex,
+    /// The endpoint inside the start region.
+    pub from: VertexIndex,
+    pub to: VertexIndex,
+    /// Obstacles blocking the edge, in ascending order.
+    pub blocked_by: Vec<ObstacleId>,
+    /// Obstacles containing `to`, in ascending order.
+    pub to_contained_by: Vec<ObstacleId>,
+    /// True if one-way rules forbid going from `from` to `to`.
+    pub one_way: bool,
+    /// True if `to` can reach the goal, so that unblocking this edge alone would connect start and goal.
+    pub into_goal_region: bool,
+}
+
+/// Why a path query failed.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum Diagnosis {
+    /// There is a path, nothing to explain.
+    Reachable,
+    /// The start or goal vertex lies inside these obstacles.
+    BlockedEndpoint { vertex: VertexIndex, obstacles: Vec<ObstacleId> },
+    /// The regions are separated by obstacles or one-way rules, see the frontier.
+    Obstacles,
+    /// A straight segment between the regions is free of obstacles, but longer than the connection radius,
+    /// so the roadmap is too sparse to cross the gap. `length` is the length of the shortest such segment found.
+    Sparse { from: VertexIndex, to: VertexIndex, length: f64 },
+}
+
+/// The result of `DPrm::explain_path`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct PathExplanation {
+    pub start_region: Region,
+    pub goal_region: Region,
+    /// Edges leaving the start region, in ascending edge order.
+    pub frontier: Vec<FrontierEdge>,
+    pub diagnosis: Diagnosis,
+}
+
+/// Vertices bucketed into square cells, to find nearest vertices without comparing every pair.
+pub(crate) struct Grid {
+    cell: f64,
+    cells: HashMap<(i64, i64), Vec<VertexIndex>>,
+    min: (i64, i64),
+    max: (i64, i64),
+}
+
+impl Grid {
+    pub(crate) fn new(vertices: &[VertexIndex], points: &[Point<f64>], cell: f64) -> Grid {
+        let mut grid = Grid { cell, cells: HashMap::new(), min: (i64::MAX, i64::MAX), max: (i64::MIN, i64::MIN) };
+        for v in vertices {
+            let key = grid.key(&points[*v]);
+            grid.min = (grid.min.0.min(key.0), grid.min.1.min(key.1));
+            grid.max = (grid.max.0.max(key.0), grid.max.1.max(key.1));
+            grid.cells.entry(key).or_default().push(*v);
+        }
+        grid
+    }
+
+    fn key(&self, point: &Point<f64>) -> (i64, i64) {
+        ((point.x() / self.cell).floor() as i64, (point.y() / self.cell).floor() as i64)
+    }
+
+    /// Returns the distance to and the index of the nearest vertex farther away than `min_length`
+    /// and closer than `max_length`, searching rings of cells around the point until no closer vertex can be found.
+    pub(crate) fn nearest_beyond(
+        &self,
+        point: &Point<f64>,
+        points: &[Point<f64>],
+        min_length: f64,
+        max_length: f64,
+    ) -> Option<(f64, VertexIndex)> {
+        let (x, y) = self.key(point);
+        let rings = [x - self.min.0, self.max.0 - x, y - self.min.1, self.max.1 - y].into_iter().max()?.max(0);
+        let mut nearest: Option<(f64, VertexIndex)> = None;
+        for k in 0..=rings {
+            // Every vertex in ring k is at least (k - 1) cells away
+            let ring_length = (k - 1) as f64 * self.cell;
+            if ring_length >= max_length || nearest.is_some_and(|(length, _)| length <= ring_length) {
+                break;
+            }
+            for key in Self::ring((x, y), k) {
+                for v in self.cells.get(&key).into_iter().flatten() {
+                    let length = point.euclidean_distance(&points[*v]);
+                    if length > min_length && length < max_length && nearest.is_none_or(|(best, _)| length < best) {
+                        nearest = Some((length, *v));
+                    }
+                }
+            }
+        }
+        nearest
+    }
+
+    /// Returns the cells exactly k cells away from the center, in either axis.
+    fn ring((x, y): (i64, i64), k: i64) -> Vec<(i64, i64)> {
+        if k == 0 {
+            return vec![(x, y)];
+        }
+        let mut cells = Vec::with_capacity(8 * k as usize);
+        for i in (x - k)..=(x + k) {
+            cells.push((i, y - k));
+            cells.push((i, y + k));
+        }
+        for j in (y - k + 1)..(y + k) {
+            cells.push((x - k, j));
+            cells.push((x + k, j));
+        }
+        cells
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::Rect;
+
+    fn cfg() -> PrmConfig {
+        PrmConfig::new(150, 100, 100, [7u8; 32], 2)
+    }
+
+    #[tokio::test]
+    async fn explain_path_blames_obstacles_or_sparsity() {
+        let wall = Obstacle { rect: Rect::new((45.0, -10.0), (55.0, 110.0)), id: 9 };
+        let dprm = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: vec![wall] }).await;
+        let n = dprm.vertices.len();
+        let west = (0..n).find(|v| dprm.vertices[*v].x() < 40.0).unwrap();
+        let east = (0..n).find(|v| dprm.vertices[*v].x() > 60.0).unwrap();
+        assert!(dprm.run_astar(&west, &east).is_none());
+        let explanation = dprm.explain_path(&west, &east);
+        assert_eq!(explanation.diagnosis, Diagnosis::Obstacles);
+        assert!(explanation.start_region.contains(west) && explanation.goal_region.contains(east));
+        assert!(explanation.start_region.vertices.iter().all(|v| !explanation.goal_region.contains(*v)));
+        let crossing: Vec<_> = explanation.frontier.iter().filter(|f| f.into_goal_region).collect();
+        assert!(!crossing.is_empty());
+        assert!(crossing.iter().all(|f| f.blocked_by == vec![9] && !f.one_way));
+        assert_eq!(dprm.explain_path(&west, &west).diagnosis, Diagnosis::Reachable);
+
+        let inside = dprm.find_contained_by_obstacle(&wall, 0.0)[0];
+        assert_eq!(
+            dprm.explain_path(&west, &inside).diagnosis,
+            Diagnosis::BlockedEndpoint { vertex: inside, obstacles: vec![9] }
+        );
+
+        // Without obstacles, only sparsity can separate vertices
+        let sparse = DPrm::from_cfg(PrmConfig::new(12, 100, 100, [7u8; 32], 2), ObstacleSet { obstacles: Vec::new() }).await;
+        let separated = (0..12).flat_map(|a| (0..12).map(move |b| (a, b))).find(|(a, b)| sparse.run_astar(a, b).is_none());
+        let (a, b) = separated.expect("12 vertices should not be connected");
+        let explanation = sparse.explain_path(&a, &b);
+        assert!(explanation.frontier.is_empty());
+        match explanation.diagnosis {
+            Diagnosis::Sparse { from, to, length } => {
+                assert!(explanation.start_region.contains(from) && explanation.goal_region.contains(to));
+                assert!(length > sparse.max_radius());
+            }
+            diagnosis => panic!("expected a sparse roadmap, got {:?}", diagnosis),
+        }
+    }
+}
@@ -1,11 +1,33 @@
+mod blocking;
+mod chunked;
+mod cost;
 mod dprm;
+mod explain;
 mod prm;
+pub mod protocol;
+mod recorder;
+mod roadmap;
+mod scenario;
+mod shared;
+mod temporal;
+mod traffic;
+mod transaction;
 pub mod prelude {
     use serde::{Deserialize, Serialize};
+    pub use crate::blocking::{Blockings, FootprintIndex};
+    pub use crate::cost::*;
     pub use crate::dprm::*;
+    pub use crate::explain::*;
     pub use crate::prm::Prm;
-
-    use geo::{Contains, Intersects};
+    pub use crate::recorder::*;
+    pub use crate::roadmap::*;
+    pub use crate::scenario::*;
+    pub use crate::shared::*;
+    pub use crate::temporal::*;
+    pub use crate::traffic::{Direction, DirectionZone, ZoneId};
+    pub use crate::transaction::Transaction;
+
+    use geo::{EuclideanDistance, Intersects};
     use geo::{Line, Point, Rect};
     use plotters::prelude::*;
     use rand::{prelude::*};
@@ -14,6 +36,8 @@ pub mod prelude {
     pub type EdgeIndex = usize;
     pub type VertexIndex = usize;
     pub type ObstacleId = u128;
+    /// Group of obstacles that are enabled and disabled together, see `DPrm::disable_layer`.
+    pub type LayerId = u32;
 
     #[derive(Clone, Serialize, Deserialize, Debug)]
     pub struct Edge {
@@ -47,6 +71,7 @@ pub mod prelude {
     }
 
     impl Obstacle {
+        #[allow(clippy::too_many_arguments)]
         pub fn new_random(rng: &mut ChaCha8Rng, obstacle_max_size: f64, obstacle_min_size: f64, x_min: f64, y_min: f64, x_max: f64, y_max: f64, id: u128) -> Obstacle {
             let x_pos = rng.gen_range(x_min..(x_max));
             let y_pos = rng.gen_range(y_min..(y_max));
@@ -70,12 +95,28 @@ pub mod prelude {
             self.id
         }
 
-        fn contains(&self, point: &Point<f64>) -> bool {
-            self.rect.contains(point)
+        pub fn contains(&self, point: &Point<f64>) -> bool {
+            self.contains_with_clearance(point, 0.0)
         }
 
         pub fn intersects(&self, edge: &Line<f64>) -> bool {
+            self.intersects_with_clearance(edge, 0.0)
+        }
+
+        /// Returns true if the point is at most `clearance` from the obstacle,
+        /// i.e. inside or on the boundary of the obstacle inflated by a disc of radius `clearance`.
+        /// Touching counts as a collision here and in `intersects_with_clearance`, so a vertex is contained
+        /// exactly when its edges can not leave it.
+        pub fn contains_with_clearance(&self, point: &Point<f64>, clearance: f64) -> bool {
+            self.rect.intersects(point)
+                || (clearance > 0.0 && self.rect.to_lines().iter().any(|side| side.euclidean_distance(point) <= clearance))
+        }
+
+        /// Returns true if the edge comes within `clearance` of the obstacle,
+        /// i.e. intersects the obstacle inflated by a disc of radius `clearance`, boundary included.
+        pub fn intersects_with_clearance(&self, edge: &Line<f64>, clearance: f64) -> bool {
             self.rect.intersects(edge)
+                || (clearance > 0.0 && self.rect.to_lines().iter().any(|side| side.euclidean_distance(edge) <= clearance))
         }
 
         pub fn rectangle(&self) -> Rectangle<(f64, f64)> {
@@ -92,6 +133,7 @@ pub mod prelude {
     }
 
     impl ObstacleSet {
+        #[allow(clippy::too_many_arguments)]
         pub fn new_random(
             n: usize,
             obstacle_max_size: f64, obstacle_min_size: f64, x_min: f64, y_min: f64, x_max: f64, y_max: f64,
@@ -105,11 +147,19 @@ pub mod prelude {
         }
 
         pub fn contains(&self, point: &Point<f64>) -> bool {
-            self.obstacles.iter().any(|o| o.contains(point))
+            self.contains_with_clearance(point, 0.0)
         }
 
         pub fn intersects(&self, edge: &Line<f64>) -> bool {
-            self.obstacles.iter().any(|o| o.intersects(edge))
+            self.intersects_with_clearance(edge, 0.0)
+        }
+
+        pub fn contains_with_clearance(&self, point: &Point<f64>, clearance: f64) -> bool {
+            self.obstacles.iter().any(|o| o.contains_with_clearance(point, clearance))
+        }
+
+        pub fn intersects_with_clearance(&self, edge: &Line<f64>, clearance: f64) -> bool {
+            self.obstacles.iter().any(|o| o.intersects_with_clearance(edge, clearance))
         }
 
         pub fn remove(&mut self, obstacle: &Obstacle) {
@@ -125,6 +175,10 @@ pub mod prelude {
         }
     }
 
+    /// Configuration of a roadmap.
+    /// `threads` is the number of worker tasks used to build and update it, 0 uses one per available core.
+    /// `robot_radius` is the radius of a disc covering the robot. Obstacles are inflated by it in every
+    /// collision check, 0 treats the robot as a point.
     #[derive(Clone, Serialize, Deserialize, Debug)]
     pub struct PrmConfig {
         pub num_vertices: usize,
@@ -134,6 +188,8 @@ pub mod prelude {
         pub use_viable_edges: bool,
         pub use_blocked_per_obstacle: bool,
         pub threads: usize,
+        #[serde(default)]
+        pub robot_radius: f64,
     }
 
     impl PrmConfig {
@@ -152,11 +208,20 @@ pub mod prelude {
                 use_viable_edges: false,         // Default to false
                 use_blocked_per_obstacle: false, // Default to false
                 threads,
+                robot_radius: 0.0,
+            }
+        }
+
+        /// Number of worker tasks to use, resolving 0 to the number of available cores.
+        pub fn threads(&self) -> usize {
+            match self.threads {
+                0 => std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+                threads => threads,
             }
         }
     }
 
-    #[derive(Debug, Serialize, Deserialize)]
+    #[derive(Clone, Debug, Serialize, Deserialize)]
     pub struct DPrmPath {
         pub vertices: Vec<Vertex>,
         pub length: Distance,
@@ -164,3 +229,26 @@ pub mod prelude {
 
     pub type Distance = usize;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::prelude::*;
+    use geo::{Line, Point, Rect};
+
+    #[test]
+    fn touching_an_obstacle_is_a_collision_at_any_clearance() {
+        let obstacle = Obstacle { rect: Rect::new((0.0, 0.0), (10.0, 10.0)), id: 1 };
+        for clearance in [0.0, 1.0] {
+            let touching = Point::new(5.0, -clearance);
+            let beyond = Point::new(5.0, -clearance - 0.001);
+            assert!(obstacle.contains_with_clearance(&touching, clearance));
+            assert!(!obstacle.contains_with_clearance(&beyond, clearance));
+            // An edge through a point collides exactly when the point does
+            assert!(obstacle.intersects_with_clearance(&Line::new((0.0, -clearance), (10.0, -clearance)), clearance));
+            assert!(!obstacle.intersects_with_clearance(&Line::new(beyond, Point::new(-5.0, beyond.y())), clearance));
+        }
+        let set = ObstacleSet { obstacles: vec![obstacle] };
+        assert!(set.contains(&Point::new(10.0, 5.0)));
+        assert!(set.intersects(&Line::new((10.0, 5.0), (20.0, 5.0))));
+    }
+}
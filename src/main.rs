@@ -1,17 +1,238 @@
 #![allow(unused)]
 // use pathfinding::directed::astar::astar;
+use clap::{Parser, Subcommand};
 use dynamic_prm::prelude::*;
 use geo::{Contains, Intersects};
 use geo::{Line, Point, Rect};
 use plotters::prelude::*;
 use rand::prelude::*;
 use rand_chacha::ChaCha8Rng;
-use std::io::{stdin, Stdin};
+use std::fs::File;
+use std::io::{stdin, BufReader, Stdin};
 use std::sync::Arc;
 use std::{env, time::Instant};
 
+type CliResult = Result<(), Box<dyn std::error::Error>>;
+
+/// Build, query, update and inspect DPrm roadmaps stored as .bin files.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Builds a roadmap from a PrmConfig JSON file and an optional ObstacleSet JSON file.
+    Build {
+        #[arg(long)]
+        config: String,
+        #[arg(long)]
+        obstacles: Option<String>,
+        #[arg(long)]
+        output: String,
+    },
+    /// Finds a path between the vertices nearest to two points and prints it as JSON.
+    Query {
+        #[arg(long)]
+        map: String,
+        /// Start point as "x,y"
+        #[arg(long, value_parser = parse_point)]
+        start: (f64, f64),
+        /// Goal point as "x,y"
+        #[arg(long, value_parser = parse_point)]
+        goal: (f64, f64),
+    },
+    /// Adds a rectangular obstacle and updates the map in place.
+    AddObstacle {
+        #[arg(long)]
+        map: String,
+        /// Lower left corner as "x,y"
+        #[arg(long, value_parser = parse_point)]
+        min: (f64, f64),
+        /// Upper right corner as "x,y"
+        #[arg(long, value_parser = parse_point)]
+        max: (f64, f64),
+        /// Defaults to one more than the largest id in the map
+        #[arg(long)]
+        id: Option<ObstacleId>,
+    },
+    /// Removes an obstacle by id and updates the map in place.
+    RemoveObstacle {
+        #[arg(long)]
+        map: String,
+        #[arg(long)]
+        id: ObstacleId,
+    },
+    /// Plots the map to a PNG, optionally with the path between two points.
+    Plot {
+        #[arg(long)]
+        map: String,
+        #[arg(long)]
+        output: String,
+        #[arg(long, value_parser = parse_point, requires = "goal")]
+        start: Option<(f64, f64)>,
+        #[arg(long, value_parser = parse_point, requires = "start")]
+        goal: Option<(f64, f64)>,
+    },
+    /// Prints statistics about the map as JSON.
+    Stats {
+        #[arg(long)]
+        map: String,
+    },
+    /// Checks the internal consistency of the map and prints every inconsistency as JSON.
+    Validate {
+        #[arg(long)]
+        map: String,
+        /// Rebuild the derived state and write the map back if it is inconsistent
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Replays a scenario file and writes the per-step results to CSV.
+    Replay {
+        #[arg(long)]
+        scenario: String,
+        #[arg(long, value_enum, default_value = "dprm")]
+        strategy: StrategyArg,
+        #[arg(long, default_value = "output/replay.csv")]
+        output: String,
+    },
+    /// Runs the fixed DPrm experiment.
+    Demo,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum StrategyArg {
+    Dprm,
+    Prm,
+}
+
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Build { config, obstacles, output } => build(&config, obstacles.as_deref(), &output).await,
+        Command::Query { map, start, goal } => query(&map, start, goal),
+        Command::AddObstacle { map, min, max, id } => add_obstacle(&map, min, max, id).await,
+        Command::RemoveObstacle { map, id } => remove_obstacle(&map, id),
+        Command::Plot { map, output, start, goal } => plot(&map, &output, start.zip(goal)),
+        Command::Stats { map } => stats(&map),
+        Command::Validate { map, repair } => validate(&map, repair).await,
+        Command::Replay { scenario, strategy, output } => replay_scenario(&scenario, strategy, &output).await,
+        Command::Demo => {
+            demo().await;
+            Ok(())
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/*
+    SUBCOMMANDS
+*/
+async fn build(config_path: &str, obstacles_path: Option<&str>, output: &str) -> CliResult {
+    let cfg: PrmConfig = serde_json::from_reader(BufReader::new(File::open(config_path)?))?;
+    let obstacles = match obstacles_path {
+        Some(path) => serde_json::from_reader(BufReader::new(File::open(path)?))?,
+        None => ObstacleSet { obstacles: Vec::new() },
+    };
+    let start_time = Instant::now();
+    let dprm = DPrm::from_cfg(cfg, obstacles).await;
+    eprintln!("Built dprm in {} ms", start_time.elapsed().as_millis());
+    dprm.to_file(output)
+}
+
+fn query(map: &str, start: (f64, f64), goal: (f64, f64)) -> CliResult {
+    let dprm = DPrm::from_file(map)?;
+    let start = dprm.get_nearest(Point::from(start));
+    let goal = dprm.get_nearest(Point::from(goal));
+    let path = dprm.run_astar(&start.index, &goal.index);
+    println!("{}", serde_json::to_string(&path)?);
+    Ok(())
+}
+
+async fn add_obstacle(map: &str, min: (f64, f64), max: (f64, f64), id: Option<ObstacleId>) -> CliResult {
+    let mut dprm = DPrm::from_file(map)?;
+    let id = match id {
+        Some(id) if dprm.contains_obstacle(id) => return Err(format!("Obstacle {} already exists", id).into()),
+        Some(id) => id,
+        // Obstacles of disabled layers keep their ids
+        None => match dprm.obstacles().obstacles.iter().chain(dprm.disabled_obstacles().iter()).map(|o| o.id()).max() {
+            Some(max) => max.checked_add(1).ok_or("No free obstacle id after the largest one, pass --id")?,
+            None => 0,
+        },
+    };
+    let obstacle = Obstacle { rect: Rect::new(min, max), id };
+    let blockings = dprm.find_blocked_by_obstacle(obstacle).await;
+    let newly_blocked = dprm.insert_blocked_by_obstacle(obstacle, blockings);
+    if !dprm.contains_obstacle(id) {
+        return Err(format!("Obstacle {} was not inserted", id).into());
+    }
+    eprintln!("Added obstacle {}, blocking {} more edges", id, newly_blocked.len());
+    overwrite_map(&dprm, map)
+}
+
+fn remove_obstacle(map: &str, id: ObstacleId) -> CliResult {
+    let mut dprm = DPrm::from_file(map)?;
+    if !dprm.contains_obstacle(id) {
+        return Err(format!("Obstacle {} not found", id).into());
+    }
+    let newly_freed = dprm.remove_obstacle(id);
+    eprintln!("Removed obstacle {}, freeing {} edges", id, newly_freed.len());
+    overwrite_map(&dprm, map)
+}
+
+fn plot(map: &str, output: &str, endpoints: Option<((f64, f64), (f64, f64))>) -> CliResult {
+    let dprm = DPrm::from_file(map)?;
+    let path = endpoints.and_then(|(start, goal)| {
+        let start = dprm.get_nearest(Point::from(start));
+        let goal = dprm.get_nearest(Point::from(goal));
+        dprm.run_astar(&start.index, &goal.index)
+    });
+    dprm.plot(output.to_string(), path);
+    Ok(())
+}
+
+fn stats(map: &str) -> CliResult {
+    let dprm = DPrm::from_file(map)?;
+    println!("{}", serde_json::to_string_pretty(&dprm.stats())?);
+    Ok(())
+}
+
+async fn validate(map: &str, repair: bool) -> CliResult {
+    let mut dprm = DPrm::from_file(map)?;
+    let inconsistencies = dprm.validate();
+    println!("{}", serde_json::to_string_pretty(&inconsistencies)?);
+    if inconsistencies.is_empty() {
+        return Ok(());
+    }
+    if !repair {
+        return Err(format!("Found {} inconsistencies", inconsistencies.len()).into());
+    }
+    dprm.rebuild_derived_state().await;
+    eprintln!("Rebuilt derived state");
+    overwrite_map(&dprm, map)
+}
+
+/// Replays the scenario at the given path and writes the per-step results to CSV.
+async fn replay_scenario(scenario_path: &str, strategy: StrategyArg, output: &str) -> CliResult {
+    let scenario = Scenario::from_file(scenario_path)?;
+    let strategy = match strategy {
+        StrategyArg::Dprm => Strategy::DPrm,
+        StrategyArg::Prm => Strategy::Prm,
+    };
+    println!("* Replaying {} with {:?}", scenario_path, strategy);
+    let results = scenario.replay(strategy).await;
+    write_csv(&results, output)?;
+    println!("* Wrote {} steps to {}", results.len(), output);
+    Ok(())
+}
+
+async fn demo() {
     /*
     // Experiment params:
     let iterations: usize = parse_env_var("ITERATIONS");
@@ -164,6 +385,27 @@ async fn main() {
 /*
     HELPER FUNCTIONS
 */
+/// Writes the map next to the original and renames it over it, so a failed write leaves the original intact.
+fn overwrite_map(dprm: &DPrm, map: &str) -> CliResult {
+    let temp = format!("{}.tmp", map);
+    if let Err(e) = dprm.to_file(&temp) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(e);
+    }
+    std::fs::rename(&temp, map)?;
+    Ok(())
+}
+
+/// Parses a point given as "x,y".
+fn parse_point(s: &str) -> Result<(f64, f64), String> {
+    let (x, y) = s
+        .split_once(',')
+        .ok_or_else(|| format!("expected \"x,y\", got \"{}\"", s))?;
+    let x = x.trim().parse::<f64>().map_err(|e| e.to_string())?;
+    let y = y.trim().parse::<f64>().map_err(|e| e.to_string())?;
+    Ok((x, y))
+}
+
 fn parse_env_var(name: &str) -> usize {
     env::var(name)
         .unwrap_or_else(|_| panic!("Environment variable {} not set", name))
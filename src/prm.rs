@@ -2,6 +2,7 @@
 use geo::{Contains, EuclideanDistance, Intersects, Line, Point, Rect};
 // use pathfinding::directed::astar::astar;
 use crate::prelude::*;
+use pathfinding::directed::astar::astar;
 use plotters::prelude::*;
 use rand::{prelude::*, seq::index};
 use rand_chacha::ChaCha8Rng;
@@ -29,11 +30,12 @@ pub struct Prm {
     pub viable_edges: Arc<Vec<Edge>>,
     pub obstacles: Arc<ObstacleSet>,
     pub cfg: PrmConfig,
+    neighbors: Arc<Neighbors>,
 }
 
 impl Prm {
     pub async fn from_cfg(cfg: PrmConfig, obstacles: ObstacleSet) -> Prm {
-        let threads = cfg.threads;
+        let threads = cfg.threads();
         let mut prm =
             Prm {
                 vertices: Arc::new(Vec::new()),
@@ -41,6 +43,7 @@ impl Prm {
                 viable_edges: Arc::new(Vec::new()),
                 obstacles: Arc::new(obstacles),
                 cfg,
+                neighbors: Arc::new(Neighbors::new()),
             };
         prm.compute(threads).await;
         prm
@@ -51,7 +54,7 @@ impl Prm {
         let mut nearest = self.vertices[0].clone();
         for v in self.vertices.iter() {
             let distance = v.point.euclidean_distance(&point);
-            if distance < min_distance && !self.obstacles.contains(&v.point) {
+            if distance < min_distance && !self.obstacles.contains_with_clearance(&v.point, self.cfg.robot_radius) {
                 min_distance = distance;
                 nearest = v.clone();
             }
@@ -71,8 +74,8 @@ impl Prm {
 
     pub fn increment_seed(&self) {
         let mut seed = self.cfg.seed; // Borrow a mutable reference
-        for i in 0..seed.len() {
-            seed[i] = seed[i].wrapping_add(1);
+        for byte in seed.iter_mut() {
+            *byte = byte.wrapping_add(1);
         }
     }
 
@@ -106,10 +109,39 @@ impl Prm {
         self.vertices = Arc::new(vertices);
         self.edges = Arc::new(edges);
         self.viable_edges = Arc::new(viable_edges);
+        self.initialize_neighbors();
     }
 
-    /// Returns the set of vertices and edges to be removed
-    pub async fn remove_edges(&self, obstacle: Obstacle, num_threads: usize) -> Vec<Edge> {
+    /// Rebuilds the adjacency structure used by A* from the current edges.
+    fn initialize_neighbors(&mut self) {
+        let mut neighbors = Neighbors::new();
+        for e in self.edges.iter() {
+            neighbors.add(e);
+        }
+        self.neighbors = Arc::new(neighbors);
+    }
+
+    /// Runs the A* algorithm on the adjacency structure built from the current edges.
+    /// There is no path from or to a vertex inside an obstacle, as in DPrm.
+    pub fn run_astar(&self, start: &VertexIndex, end: &VertexIndex) -> Option<DPrmPath> {
+        if [start, end].iter().any(|v| self.obstacles.contains_with_clearance(&self.vertices[**v].point, self.cfg.robot_radius)) {
+            return None;
+        }
+        let end_point = self.vertices[*end].point;
+        let (path, length) = astar(
+            start,
+            |v| self.neighbors.get(v).clone(),
+            |v| self.vertices[*v].point.euclidean_distance(&end_point).round() as Distance,
+            |v| *v == *end,
+        )?;
+        Some(DPrmPath {
+            vertices: path.into_iter().map(|i| self.vertices[i].clone()).collect(),
+            length,
+        })
+    }
+
+    /// Returns the indices in `edges` of the edges blocked by the obstacle, in ascending order.
+    pub async fn remove_edges(&self, obstacle: Obstacle, num_threads: usize) -> Vec<usize> {
         let chunk_size = self.edges.len() / num_threads;
         let mut handles = Vec::new();
         for i in 0..num_threads {
@@ -141,29 +173,33 @@ impl Prm {
         remove_edges
     }
 
+    /// Adds an obstacle to the PRM and moves the edges it blocks out of `edges`.
+    /// Runs in O(E / threads) for the intersection tests plus O(B) for the B blocked edges.
     pub async fn add_obstacle(&mut self, obstacle: Obstacle, num_threads: usize) {
         let blocked_edges = self.remove_edges(obstacle, num_threads).await;
         // println!("Removing {} edges", remove_edges.len());
-        let mut edges = (*self.edges).clone();
-        edges.retain(|e| !&blocked_edges.contains(e));
-        if self.cfg.use_viable_edges {
-            let mut viable_edges = (*self.viable_edges).clone();
-            viable_edges.extend(blocked_edges);
-            self.viable_edges = Arc::new(viable_edges);
+        // The workers have dropped their clones, so make_mut does not copy
+        let edges = Arc::make_mut(&mut self.edges);
+        let viable_edges = Arc::make_mut(&mut self.viable_edges);
+        let neighbors = Arc::make_mut(&mut self.neighbors);
+        // Swap-remove from the back, so that the remaining indices stay valid
+        for index in blocked_edges.into_iter().rev() {
+            let edge = edges.swap_remove(index);
+            neighbors.remove(&edge);
+            if self.cfg.use_viable_edges {
+                viable_edges.push(edge);
+            }
         }
-        self.edges = Arc::new(edges);
-        let mut obstacles = (*self.obstacles).clone();
-        obstacles.obstacles.push(obstacle);
-        self.obstacles = Arc::new(obstacles);
+        Arc::make_mut(&mut self.obstacles).add(obstacle);
     }
 
-    // Returns subset of edges/vertices to be removed
-    async fn remove_edges_worker(&self, start: usize, end: usize, obstacle: Obstacle) -> Vec<Edge> {
+    // Returns the indices of the edges to be removed
+    async fn remove_edges_worker(&self, start: usize, end: usize, obstacle: Obstacle) -> Vec<usize> {
         let mut remove_edges = Vec::new();
         // println!("Worker comparing {} edges", end-start);
         for i in start..end {
-            if obstacle.intersects(&self.edges[i].line) {
-                remove_edges.push(self.edges[i].clone());
+            if obstacle.intersects_with_clearance(&self.edges[i].line, self.cfg.robot_radius) {
+                remove_edges.push(i);
             }
         }
         // println!("Worker found {} edges to remove", remove_edges.len());
@@ -179,13 +215,14 @@ impl Prm {
     ) -> (Vec<usize>) {
         let mut new_edges = Vec::new();
         let n = self.viable_edges.len();
-        let chunk_size = (n + num_workers - 1) / num_workers;
+        let chunk_size = n.div_ceil(num_workers);
         let start = worker_index * chunk_size;
         let end = ((worker_index + 1) * chunk_size).min(n); // Ensure end does not exceed n
 
         for i in start..end {
-            let e = self.viable_edges[i].clone();
-            if obstacle.intersects(&e.line) && !self.obstacles.intersects(&e.line) {
+            let e = &self.viable_edges[i];
+            if obstacle.intersects_with_clearance(&e.line, self.cfg.robot_radius)
+                && !self.obstacles.intersects_with_clearance(&e.line, self.cfg.robot_radius) {
                 new_edges.push(i)
             }
         }
@@ -193,11 +230,10 @@ impl Prm {
         new_edges
     }
 
-    /// Removes an obstacle from the PRM and computes the new set of obstacles
+    /// Removes an obstacle from the PRM and computes the new set of obstacles.
+    /// With viable edges this moves the freed edges back in O(B), otherwise PRM* is rerun.
     pub async fn remove_obstacle(&mut self, obstacle: Obstacle, num_threads: usize) {
-        let mut obstacles = (*self.obstacles).clone();
-        obstacles.remove(&obstacle);
-        self.obstacles = Arc::new(obstacles);
+        Arc::make_mut(&mut self.obstacles).remove_by_id(obstacle.id());
 
         if !self.cfg.use_viable_edges {
             // Rerun PRM* and return
@@ -206,17 +242,19 @@ impl Prm {
 
         let new_edges = self.find_new_edges(obstacle, num_threads).await;
         // println!("Adding {} edges", new_edges.len());
-        // Move the edges from viable to edges:
-        let mut edges = (*self.edges).clone();
-        let mut viable_edges = (*self.viable_edges).clone();
-        for (i, index) in new_edges.iter().enumerate() {
-            edges.push(viable_edges.remove(index - i));
+        // Move the edges from viable to edges, swap-removing from the back:
+        let edges = Arc::make_mut(&mut self.edges);
+        let viable_edges = Arc::make_mut(&mut self.viable_edges);
+        let neighbors = Arc::make_mut(&mut self.neighbors);
+        for index in new_edges.into_iter().rev() {
+            let edge = viable_edges.swap_remove(index);
+            neighbors.add(&edge);
+            edges.push(edge);
         }
-        self.edges = Arc::new(edges);
-        self.viable_edges = Arc::new(viable_edges);
     }
 
     /// obstacle must already be removed the self.obstacles
+    /// Returns the indices in `viable_edges` of the freed edges, in ascending order.
     pub async fn find_new_edges(&self, obstacle: Obstacle, num_threads: usize) -> Vec<usize> {
         assert!(!self.obstacles.obstacles.contains(&obstacle));
 
@@ -253,21 +291,19 @@ impl Prm {
         let mut viable_edges = Vec::new();
         for i in start..end {
             let p1 = vertices[i];
-            // if self.obstacles.contains(&p1) && !self.cfg.use_viable_edges {
-            //    continue;
-            //}
             vs.push(Vertex {
                 point: p1,
                 index: i,
             });
+            // Every edge of a vertex inside an obstacle intersects it, so the vertex only has viable edges
+            if self.obstacles.contains_with_clearance(&p1, self.cfg.robot_radius) && !self.cfg.use_viable_edges {
+                continue;
+            }
             for (j, p2) in vertices.iter().enumerate() {
-                if self.obstacles.contains(&p1) && !self.cfg.use_viable_edges {
-                    continue;
-                }
                 let distance = p1.euclidean_distance(p2);
                 if distance < gamma && p1 != *p2 {
                     let line = Line::new(p1, *p2);
-                    if !self.obstacles.intersects(&line) {
+                    if !self.obstacles.intersects_with_clearance(&line, self.cfg.robot_radius) {
                         edges.push(Edge {
                             line,
                             length: distance,
@@ -321,4 +357,175 @@ impl Prm {
         }
         (all_vertices, all_edges, all_viable_edges)
     }
-}
\ No newline at end of file
+}
+
+/// The free edges per vertex, updated as edges are added and removed.
+/// Prm stores an edge from its higher to its lower vertex, and also the other way round when both vertices
+/// were connected by the same worker, so only the ones going down in vertex index are entered, once per direction.
+#[derive(Clone, Debug)]
+struct Neighbors {
+    inner: HashMap<VertexIndex, Vec<(VertexIndex, Distance)>>,
+}
+const EMPTY: &Vec<(VertexIndex, Distance)> = &Vec::new();
+
+impl Neighbors {
+    fn new() -> Neighbors {
+        Neighbors {
+            inner: HashMap::new(),
+        }
+    }
+
+    // Point to an empty vector
+    fn get(&self, v: &VertexIndex) -> &Vec<(VertexIndex, Distance)> {
+        if let Some(vec) = self.inner.get(v) {
+            vec
+        } else { EMPTY }
+    }
+
+    fn add(&mut self, e: &Edge) {
+        if e.points.0 < e.points.1 {
+            return;
+        }
+        let length = e.length.round() as Distance;
+        self.insert_sorted(e.points.0, e.points.1, length);
+        self.insert_sorted(e.points.1, e.points.0, length);
+    }
+
+    /// Keeps the neighbors of a vertex sorted, so A* visits them in the same order whatever order
+    /// the edges were added in and finds the same path as on a rebuilt roadmap.
+    fn insert_sorted(&mut self, from: VertexIndex, to: VertexIndex, length: Distance) {
+        let neighbors = self.inner.entry(from).or_default();
+        let position = neighbors.partition_point(|(v, _)| *v < to);
+        neighbors.insert(position, (to, length));
+    }
+
+    fn remove(&mut self, e: &Edge) {
+        if e.points.0 < e.points.1 {
+            return;
+        }
+        self.inner.entry(e.points.0).or_default().retain(|(v, _)| *v != e.points.1);
+        self.inner.entry(e.points.1).or_default().retain(|(v, _)| *v != e.points.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::{any, prop_assert_eq, prop_oneof, proptest, ProptestConfig};
+    use proptest::{collection::vec, strategy::Strategy};
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        Insert(Rect<f64>),
+        /// Removes the obstacle at this position, modulo the number of obstacles.
+        Remove(usize),
+    }
+
+    fn cfg() -> PrmConfig {
+        PrmConfig { use_viable_edges: true, ..PrmConfig::new(150, 20, 20, [7u8; 32], 2) }
+    }
+
+    fn rect() -> impl Strategy<Value = Rect<f64>> {
+        (0.0..20.0, 0.0..20.0, 0.5..6.0, 0.5..6.0)
+            .prop_map(|(x, y, w, h): (f64, f64, f64, f64)| Rect::new((x, y), (x + w, y + h)))
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![rect().prop_map(Op::Insert), (0..usize::MAX).prop_map(Op::Remove)]
+    }
+
+    /// The vertex pairs of the edges, which are stored in a different order after updates.
+    fn sorted_points(edges: &[Edge]) -> Vec<(VertexIndex, VertexIndex)> {
+        let mut points: Vec<_> = edges.iter().map(|e| e.points).collect();
+        points.sort();
+        points
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        /// Any sequence of insertions and removals gives the same roadmap as building it
+        /// from scratch with the final set of obstacles.
+        #[test]
+        fn updates_match_recomputation(use_viable_edges in any::<bool>(), initial in vec(rect(), 0..4), ops in vec(op(), 1..12)) {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            let cfg = PrmConfig { use_viable_edges, ..cfg() };
+            let initial = ObstacleSet {
+                obstacles: initial.into_iter().enumerate().map(|(i, rect)| Obstacle { rect, id: i as ObstacleId }).collect(),
+            };
+            let mut next_id = initial.obstacles.len() as ObstacleId;
+            let mut updated = runtime.block_on(Prm::from_cfg(cfg.clone(), initial));
+            for op in ops {
+                match op {
+                    Op::Insert(rect) => {
+                        let obstacle = Obstacle { rect, id: next_id };
+                        next_id += 1;
+                        runtime.block_on(updated.add_obstacle(obstacle, 2));
+                    }
+                    Op::Remove(i) => {
+                        let obstacles = &updated.obstacles.obstacles;
+                        if !obstacles.is_empty() {
+                            let obstacle = obstacles[i % obstacles.len()];
+                            runtime.block_on(updated.remove_obstacle(obstacle, 2));
+                        }
+                    }
+                }
+            }
+            let fresh = runtime.block_on(Prm::from_cfg(cfg, ObstacleSet::clone(&updated.obstacles)));
+            prop_assert_eq!(sorted_points(&updated.edges), sorted_points(&fresh.edges));
+            prop_assert_eq!(sorted_points(&updated.viable_edges), sorted_points(&fresh.viable_edges));
+            let n = fresh.vertices.len();
+            for (start, end) in [(0, n - 1), (1, n / 2), (n / 3, 2 * n / 3)] {
+                prop_assert_eq!(
+                    updated.run_astar(&start, &end).map(|p| p.length),
+                    fresh.run_astar(&start, &end).map(|p| p.length)
+                );
+            }
+        }
+    }
+
+    /// Every vertex lists each neighbor once, and every neighbor is a free edge.
+    fn assert_adjacency_matches_edges(prm: &Prm) {
+        let pairs: HashMap<(VertexIndex, VertexIndex), Distance> =
+            prm.edges.iter().map(|e| ((e.points.0, e.points.1), e.length.round() as Distance)).collect();
+        for v in 0..prm.vertices.len() {
+            let mut targets: Vec<VertexIndex> = prm.neighbors.get(&v).iter().map(|(to, _)| *to).collect();
+            let count = targets.len();
+            targets.sort();
+            targets.dedup();
+            assert_eq!(targets.len(), count, "duplicate neighbors of {}", v);
+            for (to, length) in prm.neighbors.get(&v) {
+                let pair = (v.max(*to), v.min(*to));
+                assert_eq!(pairs.get(&pair), Some(length));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn paths_follow_free_edges_once() {
+        let wall = Obstacle { rect: Rect::new((9.0, 4.0), (11.0, 20.0)), id: 1 };
+        let mut prm = Prm::from_cfg(cfg(), ObstacleSet { obstacles: Vec::new() }).await;
+        assert!(prm.edges.iter().any(|e| e.points.0 < e.points.1), "no edge is stored twice");
+        assert_adjacency_matches_edges(&prm);
+        prm.add_obstacle(wall, 2).await;
+        assert_adjacency_matches_edges(&prm);
+
+        let (start, end) = (prm.get_nearest(Point::new(2.0, 16.0)).index, prm.get_nearest(Point::new(18.0, 16.0)).index);
+        let path = prm.run_astar(&start, &end).unwrap();
+        assert_eq!(path.vertices.first().unwrap().index, start);
+        assert_eq!(path.vertices.last().unwrap().index, end);
+        let mut length = 0;
+        for step in path.vertices.windows(2) {
+            let (a, b) = (step[0].index, step[1].index);
+            let edge = prm.edges.iter().find(|e| e.points == (a.max(b), a.min(b))).unwrap();
+            assert!(!wall.intersects_with_clearance(&edge.line, 0.0));
+            length += edge.length.round() as Distance;
+        }
+        assert_eq!(path.length, length);
+
+        prm.remove_obstacle(wall, 2).await;
+        assert_adjacency_matches_edges(&prm);
+        assert!(prm.run_astar(&start, &end).unwrap().length <= path.length);
+    }
+}
+
@@ -0,0 +1,72 @@
+//! Messages of the line-delimited JSON protocol spoken by the planning server.
+//! Every request and response is a single JSON object on its own line.
+use crate::prelude::*;
+use geo::Rect;
+use serde::{Deserialize, Serialize};
+
+/// A request to the planning server, addressed to one of its named maps.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Request {
+    /// Finds a path between the vertices nearest to `start` and `goal`.
+    Plan {
+        map: String,
+        start: (f64, f64),
+        goal: (f64, f64),
+    },
+    AddObstacle {
+        map: String,
+        obstacle: Obstacle,
+    },
+    RemoveObstacle {
+        map: String,
+        id: ObstacleId,
+    },
+    /// Moves the obstacle with the given id to `rect`, keeping its id.
+    MoveObstacle {
+        map: String,
+        id: ObstacleId,
+        rect: Rect<f64>,
+    },
+    Nearest {
+        map: String,
+        point: (f64, f64),
+    },
+    Stats {
+        map: String,
+    },
+    /// Writes the map to a file with the given name in the snapshot directory of the server.
+    Snapshot {
+        map: String,
+        path: String,
+    },
+}
+
+/// The response to a single Request.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Response {
+    /// `path` is None if no path exists, `version` is the version of the map it was planned on.
+    Path { path: Option<DPrmPath>, version: Version },
+    /// Number of edges that changed between blocked and free, and the version of the map with the change.
+    Updated { changed_edges: usize, version: Version },
+    Nearest { vertex: Vertex },
+    Stats { stats: DPrmStats },
+    Saved { path: String },
+    Error { message: String },
+}
+
+impl Request {
+    /// Name of the map the request is addressed to.
+    pub fn map(&self) -> &str {
+        match self {
+            Request::Plan { map, .. }
+            | Request::AddObstacle { map, .. }
+            | Request::RemoveObstacle { map, .. }
+            | Request::MoveObstacle { map, .. }
+            | Request::Nearest { map, .. }
+            | Request::Stats { map }
+            | Request::Snapshot { map, .. } => map,
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use crate::prelude::*;
+use plotters::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A single change to the obstacle set of a DPrm.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub enum TimelineEvent {
+    Insert(Obstacle),
+    Remove(ObstacleId),
+}
+
+/// Output format of a recording.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+pub enum RecordingFormat {
+    /// A single animated GIF, showing each frame for `frame_delay_ms` milliseconds.
+    Gif { frame_delay_ms: u32 },
+    /// One PNG per frame, numbered `<output>_0000.png`, `<output>_0001.png`, ...
+    PngSequence,
+}
+
+/// Records how a DPrm changes while a timeline of obstacle events is applied to it.
+/// The first frame shows the initial roadmap, every following frame shows the roadmap
+/// after one event with the newly blocked edges in red and the newly freed edges in green.
+#[derive(Clone, Debug)]
+pub struct Recorder {
+    timeline: Vec<TimelineEvent>,
+    format: RecordingFormat,
+    size: (u32, u32),
+    replan: Option<(VertexIndex, VertexIndex)>,
+}
+
+impl Recorder {
+    /// Create a new Recorder for the given timeline, rendering 800x800 frames.
+    pub fn new(timeline: Vec<TimelineEvent>, format: RecordingFormat) -> Recorder {
+        Recorder {
+            timeline,
+            format,
+            size: (800, 800),
+            replan: None,
+        }
+    }
+
+    /// Sets the size of each frame in pixels.
+    pub fn with_size(mut self, width: u32, height: u32) -> Recorder {
+        self.size = (width, height);
+        self
+    }
+
+    /// Replans the path between `start` and `end` for each frame and draws it.
+    pub fn with_replanning(mut self, start: VertexIndex, end: VertexIndex) -> Recorder {
+        self.replan = Some((start, end));
+        self
+    }
+
+    /// Applies the timeline to `dprm` and renders one frame per event.
+    /// For a GIF `output` is the file name, for a PNG sequence it is the prefix of the file names.
+    /// Returns the number of frames written.
+    pub async fn record(&self, dprm: &mut DPrm, output: &str) -> Result<usize, Box<dyn std::error::Error>> {
+        match self.format {
+            RecordingFormat::Gif { frame_delay_ms } => {
+                let root = BitMapBackend::gif(output, self.size, frame_delay_ms)?.into_drawing_area();
+                self.record_frames(dprm, |_, dprm, caption, path, blocked, freed| {
+                    dprm.draw(&root, caption, path, blocked, freed)?;
+                    root.present()?;
+                    Ok(())
+                })
+                .await
+            }
+            RecordingFormat::PngSequence => {
+                self.record_frames(dprm, |frame, dprm, caption, path, blocked, freed| {
+                    let file_name = format!("{}_{:04}.png", output, frame);
+                    let root = BitMapBackend::new(&file_name, self.size).into_drawing_area();
+                    dprm.draw(&root, caption, path, blocked, freed)?;
+                    root.present()?;
+                    Ok(())
+                })
+                .await
+            }
+        }
+    }
+
+    async fn record_frames<F>(&self, dprm: &mut DPrm, mut draw_frame: F) -> Result<usize, Box<dyn std::error::Error>>
+    where
+        F: FnMut(usize, &DPrm, &str, Option<&DPrmPath>, &[EdgeIndex], &[EdgeIndex]) -> Result<(), Box<dyn std::error::Error>>,
+    {
+        let path = self.plan(dprm);
+        draw_frame(0, dprm, "Initial roadmap", path.as_ref(), &[], &[])?;
+        for (i, event) in self.timeline.iter().enumerate() {
+            let (caption, newly_blocked, newly_freed) = match event {
+                TimelineEvent::Insert(obstacle) => {
+                    let blockings = dprm.find_blocked_by_obstacle(*obstacle).await;
+                    let newly_blocked = dprm.insert_blocked_by_obstacle(*obstacle, blockings);
+                    (format!("Inserted obstacle {}", obstacle.id()), newly_blocked, Vec::new())
+                }
+                TimelineEvent::Remove(oid) => {
+                    let newly_freed = dprm.remove_obstacle(*oid);
+                    (format!("Removed obstacle {}", oid), Vec::new(), newly_freed)
+                }
+            };
+            let path = self.plan(dprm);
+            draw_frame(i + 1, dprm, &caption, path.as_ref(), &newly_blocked, &newly_freed)?;
+        }
+        Ok(self.timeline.len() + 1)
+    }
+
+    fn plan(&self, dprm: &DPrm) -> Option<DPrmPath> {
+        self.replan
+            .and_then(|(start, end)| dprm.run_astar(&start, &end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::Rect;
+
+    #[tokio::test]
+    async fn records_one_frame_per_event() {
+        let dir = std::env::temp_dir().join(format!("dynamic_prm_recorder_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut dprm = DPrm::from_cfg(PrmConfig::new(150, 100, 100, [7u8; 32], 2), ObstacleSet { obstacles: Vec::new() }).await;
+        let obstacle = Obstacle { rect: Rect::new((40.0, 40.0), (60.0, 60.0)), id: 1 };
+        let timeline = vec![TimelineEvent::Insert(obstacle), TimelineEvent::Remove(1)];
+
+        let prefix = dir.join("frame").to_string_lossy().to_string();
+        let recorder = Recorder::new(timeline.clone(), RecordingFormat::PngSequence).with_size(200, 200).with_replanning(0, 1);
+        assert_eq!(recorder.record(&mut dprm, &prefix).await.unwrap(), 3);
+        for frame in 0..3 {
+            assert!(dir.join(format!("frame_{:04}.png", frame)).is_file());
+        }
+        assert!(!dir.join("frame_0003.png").exists());
+        assert!(!dprm.contains_obstacle(1));
+
+        let gif = dir.join("recording.gif").to_string_lossy().to_string();
+        let recorder = Recorder::new(timeline, RecordingFormat::Gif { frame_delay_ms: 100 }).with_size(200, 200);
+        assert_eq!(recorder.record(&mut dprm, &gif).await.unwrap(), 3);
+        assert!(std::fs::metadata(&gif).unwrap().len() > 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
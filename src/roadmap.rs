@@ -0,0 +1,163 @@
+use crate::prelude::*;
+use geo::Point;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+
+/// Common interface of the roadmap strategies, so that they can be swapped generically.
+/// Method names differ from the inherent methods of Prm and DPrm, which take strategy specific arguments.
+pub trait Roadmap: Sized + Send + Sync {
+    /// Builds the roadmap for the given configuration and initial obstacles.
+    fn build(cfg: PrmConfig, obstacles: ObstacleSet) -> impl Future<Output = Self> + Send;
+
+    /// Adds the obstacle and updates the roadmap.
+    /// Returns false if the id is already in use.
+    fn insert_obstacle(&mut self, obstacle: Obstacle) -> impl Future<Output = bool> + Send;
+
+    /// Removes the obstacle with the given id and updates the roadmap.
+    /// Returns false if there was no such obstacle.
+    fn remove_obstacle_by_id(&mut self, oid: ObstacleId) -> impl Future<Output = bool> + Send;
+
+    /// Returns the vertex nearest to the given point that is not inside an obstacle.
+    fn nearest_vertex(&self, point: Point<f64>) -> Vertex;
+
+    /// Finds the shortest path between two vertices using the free edges.
+    fn find_path(&self, start: VertexIndex, end: VertexIndex) -> Option<DPrmPath>;
+
+    fn roadmap_stats(&self) -> RoadmapStats;
+}
+
+/// Counts shared by all roadmap strategies.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RoadmapStats {
+    pub vertices: usize,
+    pub free_edges: usize,
+    pub blocked_edges: usize,
+    pub obstacles: usize,
+}
+
+impl Roadmap for DPrm {
+    async fn build(cfg: PrmConfig, obstacles: ObstacleSet) -> DPrm {
+        DPrm::from_cfg(cfg, obstacles).await
+    }
+
+    async fn insert_obstacle(&mut self, obstacle: Obstacle) -> bool {
+        if self.contains_obstacle(obstacle.id()) {
+            return false;
+        }
+        let blockings = self.find_blocked_by_obstacle(obstacle).await;
+        self.insert_blocked_by_obstacle(obstacle, blockings);
+        true
+    }
+
+    async fn remove_obstacle_by_id(&mut self, oid: ObstacleId) -> bool {
+        if !self.contains_obstacle(oid) {
+            return false;
+        }
+        self.remove_obstacle(oid);
+        true
+    }
+
+    fn nearest_vertex(&self, point: Point<f64>) -> Vertex {
+        self.get_nearest(point)
+    }
+
+    fn find_path(&self, start: VertexIndex, end: VertexIndex) -> Option<DPrmPath> {
+        self.run_astar(&start, &end)
+    }
+
+    fn roadmap_stats(&self) -> RoadmapStats {
+        let stats = self.stats();
+        RoadmapStats {
+            vertices: stats.vertices,
+            free_edges: stats.free_edges,
+            blocked_edges: stats.viable_edges - stats.free_edges,
+            obstacles: stats.obstacles,
+        }
+    }
+}
+
+impl Roadmap for Prm {
+    async fn build(cfg: PrmConfig, obstacles: ObstacleSet) -> Prm {
+        Prm::from_cfg(cfg, obstacles).await
+    }
+
+    async fn insert_obstacle(&mut self, obstacle: Obstacle) -> bool {
+        if self.obstacles.obstacles.iter().any(|o| o.id() == obstacle.id()) {
+            return false;
+        }
+        let threads = self.cfg.threads();
+        self.add_obstacle(obstacle, threads).await;
+        true
+    }
+
+    async fn remove_obstacle_by_id(&mut self, oid: ObstacleId) -> bool {
+        let threads = self.cfg.threads();
+        match self.obstacles.obstacles.iter().find(|o| o.id() == oid).copied() {
+            Some(obstacle) => {
+                self.remove_obstacle(obstacle, threads).await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn nearest_vertex(&self, point: Point<f64>) -> Vertex {
+        self.get_nearest(point)
+    }
+
+    fn find_path(&self, start: VertexIndex, end: VertexIndex) -> Option<DPrmPath> {
+        self.run_astar(&start, &end)
+    }
+
+    /// Blocked edges are only kept when `cfg.use_viable_edges` is set.
+    /// Prm stores an edge from its higher to its lower vertex, and also the other way round when both vertices
+    /// were connected by the same worker, so only the ones going down in vertex index are counted.
+    fn roadmap_stats(&self) -> RoadmapStats {
+        let count = |edges: &[Edge]| edges.iter().filter(|e| e.points.0 > e.points.1).count();
+        RoadmapStats {
+            vertices: self.vertices.len(),
+            free_edges: count(&self.edges),
+            blocked_edges: count(&self.viable_edges),
+            obstacles: self.obstacles.obstacles.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::Rect;
+    use std::collections::HashSet;
+
+    #[tokio::test]
+    async fn prm_roadmap_stats_count_each_edge_once() {
+        let obstacles = ObstacleSet { obstacles: vec![Obstacle { rect: Rect::new((30.0, 30.0), (60.0, 60.0)), id: 1 }] };
+        let prm = <Prm as Roadmap>::build(PrmConfig { use_viable_edges: true, ..PrmConfig::new(150, 100, 100, [7u8; 32], 2) }, obstacles).await;
+        let stats = prm.roadmap_stats();
+        let pairs = |edges: &[Edge]| edges.iter().map(|e| (e.points.0.min(e.points.1), e.points.0.max(e.points.1))).collect::<HashSet<_>>().len();
+        assert_eq!(stats.free_edges, pairs(&prm.edges));
+        assert_eq!(stats.blocked_edges, pairs(&prm.viable_edges));
+        assert!(stats.blocked_edges > 0);
+    }
+
+    async fn check_ids<R: Roadmap>() {
+        let obstacle = Obstacle { rect: Rect::new((30.0, 30.0), (60.0, 60.0)), id: 1 };
+        let mut roadmap = R::build(PrmConfig::new(150, 100, 100, [7u8; 32], 2), ObstacleSet { obstacles: vec![obstacle] }).await;
+        let free_edges = roadmap.roadmap_stats().free_edges;
+        // Same id elsewhere is refused, the obstacle is removed by id whatever its rect
+        assert!(!roadmap.insert_obstacle(Obstacle { rect: Rect::new((0.0, 0.0), (10.0, 10.0)), id: 1 }).await);
+        assert_eq!(roadmap.roadmap_stats().obstacles, 1);
+        assert!(roadmap.remove_obstacle_by_id(1).await);
+        assert!(!roadmap.remove_obstacle_by_id(1).await);
+        assert_eq!(roadmap.roadmap_stats().obstacles, 0);
+        assert!(roadmap.roadmap_stats().free_edges > free_edges);
+        assert!(roadmap.insert_obstacle(obstacle).await);
+        assert_eq!(roadmap.roadmap_stats().free_edges, free_edges);
+    }
+
+    #[tokio::test]
+    async fn obstacle_ids_are_unique_in_both_strategies() {
+        check_ids::<DPrm>().await;
+        check_ids::<Prm>().await;
+    }
+}
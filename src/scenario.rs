@@ -0,0 +1,211 @@
+use crate::prelude::*;
+use geo::{Point, Rect};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    time::{Duration, Instant},
+};
+
+/// A dynamic workload: a roadmap configuration, the initial obstacles and a timeline of events.
+/// The world size is given by `cfg.width` and `cfg.height`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Scenario {
+    pub cfg: PrmConfig,
+    pub obstacles: ObstacleSet,
+    pub events: Vec<ScenarioEvent>,
+}
+
+/// A single step in the timeline of a Scenario.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub enum ScenarioEvent {
+    AddObstacle(Obstacle),
+    /// Moves the obstacle with the given id to `rect`, keeping its id.
+    MoveObstacle { id: ObstacleId, rect: Rect<f64> },
+    RemoveObstacle(ObstacleId),
+    /// Finds a path between the vertices nearest to `start` and `goal`.
+    Query { start: (f64, f64), goal: (f64, f64) },
+}
+
+/// The roadmap implementation a Scenario is replayed against.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum Strategy {
+    DPrm,
+    Prm,
+}
+
+/// Timing and outcome of one replayed step.
+/// Step 0 is the construction of the roadmap, step i is the i:th event of the Scenario.
+#[derive(Debug)]
+pub struct StepResult {
+    pub step: usize,
+    pub event: &'static str,
+    pub obstacle_id: Option<ObstacleId>,
+    pub duration: Duration,
+    /// Only set for queries that found a path.
+    pub path: Option<DPrmPath>,
+}
+
+impl Scenario {
+    /// Reads a Scenario from a JSON file at the given path.
+    pub fn from_file(file_path: &str) -> Result<Scenario, Box<dyn std::error::Error>> {
+        let file = File::open(file_path)?;
+        let reader = BufReader::new(file);
+        let scenario = serde_json::from_reader(reader)?;
+        Ok(scenario)
+    }
+
+    /// Writes the Scenario as pretty-printed JSON to the given path.
+    pub fn to_file(&self, file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let file = File::create(file_path)?;
+        let writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    /// Builds the roadmap with the given strategy and applies every event in order,
+    /// timing each step.
+    pub async fn replay(&self, strategy: Strategy) -> Vec<StepResult> {
+        match strategy {
+            Strategy::DPrm => self.replay_roadmap::<DPrm>().await,
+            Strategy::Prm => self.replay_roadmap::<Prm>().await,
+        }
+    }
+
+    /// Builds a roadmap of type R and applies every event in order, timing each step.
+    pub async fn replay_roadmap<R: Roadmap>(&self) -> Vec<StepResult> {
+        let mut results = Vec::new();
+        let start_time = Instant::now();
+        let mut roadmap = R::build(self.cfg.clone(), self.obstacles.clone()).await;
+        results.push(StepResult::new(0, "build", None, start_time.elapsed()));
+
+        for (i, event) in self.events.iter().enumerate() {
+            let step = i + 1;
+            let start_time = Instant::now();
+            let result = match event {
+                ScenarioEvent::AddObstacle(obstacle) => {
+                    if !roadmap.insert_obstacle(*obstacle).await {
+                        println!("Step {}: obstacle {} already exists", step, obstacle.id());
+                    }
+                    StepResult::new(step, "add", Some(obstacle.id()), start_time.elapsed())
+                }
+                ScenarioEvent::MoveObstacle { id, rect } => {
+                    // An obstacle that does not exist is not moved into existence
+                    if roadmap.remove_obstacle_by_id(*id).await {
+                        roadmap.insert_obstacle(Obstacle { rect: *rect, id: *id }).await;
+                    } else {
+                        println!("Step {}: obstacle {} not found", step, id);
+                    }
+                    StepResult::new(step, "move", Some(*id), start_time.elapsed())
+                }
+                ScenarioEvent::RemoveObstacle(id) => {
+                    if !roadmap.remove_obstacle_by_id(*id).await {
+                        println!("Step {}: obstacle {} not found", step, id);
+                    }
+                    StepResult::new(step, "remove", Some(*id), start_time.elapsed())
+                }
+                ScenarioEvent::Query { start, goal } => {
+                    let start = roadmap.nearest_vertex(Point::from(*start));
+                    let goal = roadmap.nearest_vertex(Point::from(*goal));
+                    let path = roadmap.find_path(start.index, goal.index);
+                    let mut result = StepResult::new(step, "query", None, start_time.elapsed());
+                    result.path = path;
+                    result
+                }
+            };
+            results.push(result);
+        }
+        results
+    }
+}
+
+impl StepResult {
+    fn new(step: usize, event: &'static str, obstacle_id: Option<ObstacleId>, duration: Duration) -> StepResult {
+        StepResult {
+            step,
+            event,
+            obstacle_id,
+            duration,
+            path: None,
+        }
+    }
+}
+
+/// Writes the results of a replay as CSV to the given path, one row per step.
+/// Path columns are left empty for steps that did not find a path.
+pub fn write_csv(results: &[StepResult], file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let file = File::create(file_path)?;
+    let mut writer = BufWriter::new(file);
+    writeln!(writer, "step,event,obstacle_id,duration_us,path_found,path_length,path_vertices")?;
+    for r in results {
+        let obstacle_id = r.obstacle_id.map(|id| id.to_string()).unwrap_or_default();
+        let (found, length, vertices) = match &r.path {
+            Some(path) => (true, path.length.to_string(), path.vertices.len().to_string()),
+            None => (false, String::new(), String::new()),
+        };
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{}",
+            r.step,
+            r.event,
+            obstacle_id,
+            r.duration.as_micros(),
+            found,
+            length,
+            vertices
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scenario() -> Scenario {
+        let rect = Rect::new((40.0, 40.0), (60.0, 60.0));
+        let query = ScenarioEvent::Query { start: (5.0, 5.0), goal: (95.0, 95.0) };
+        Scenario {
+            cfg: PrmConfig::new(150, 100, 100, [7u8; 32], 2),
+            obstacles: ObstacleSet { obstacles: Vec::new() },
+            events: vec![
+                query.clone(),
+                ScenarioEvent::AddObstacle(Obstacle { rect, id: 3 }),
+                ScenarioEvent::MoveObstacle { id: 3, rect: Rect::new((10.0, 60.0), (30.0, 80.0)) },
+                query,
+                ScenarioEvent::RemoveObstacle(3),
+                ScenarioEvent::RemoveObstacle(3),
+            ],
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_write_one_row_per_step() {
+        let path = std::env::temp_dir().join(format!("dynamic_prm_replay_{}.csv", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        for strategy in [Strategy::DPrm, Strategy::Prm] {
+            let results = scenario().replay(strategy).await;
+            write_csv(&results, &path).unwrap();
+            let csv = std::fs::read_to_string(&path).unwrap();
+            let rows: Vec<Vec<&str>> = csv.lines().map(|line| line.split(',').collect()).collect();
+            assert_eq!(rows[0], ["step", "event", "obstacle_id", "duration_us", "path_found", "path_length", "path_vertices"]);
+            assert_eq!(rows.len(), 8, "{:?}", strategy);
+            let events: Vec<(&str, &str, &str)> = rows[1..].iter().map(|row| (row[0], row[1], row[2])).collect();
+            assert_eq!(
+                events,
+                [("0", "build", ""), ("1", "query", ""), ("2", "add", "3"), ("3", "move", "3"), ("4", "query", ""), ("5", "remove", "3"), ("6", "remove", "3")]
+            );
+            for (row, result) in rows[1..].iter().zip(results.iter()) {
+                assert_eq!(row.len(), 7);
+                assert_eq!(row[4], result.path.is_some().to_string());
+                assert_eq!(row[5], result.path.as_ref().map(|p| p.length.to_string()).unwrap_or_default());
+                assert_eq!(row[6], result.path.as_ref().map(|p| p.vertices.len().to_string()).unwrap_or_default());
+            }
+            if strategy == Strategy::DPrm {
+                assert_eq!(rows[2][4], "true");
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
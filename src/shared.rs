@@ -0,0 +1,205 @@
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::ops::Deref;
+use std::sync::{Arc, RwLock};
+use tokio::sync::Mutex;
+
+/// Number of updates applied to a SharedDPrm, starting at 0.
+pub type Version = u64;
+
+/// An immutable version of a DPrm. All `&self` queries of the DPrm can be run on it.
+#[derive(Debug)]
+pub struct Snapshot {
+    version: Version,
+    dprm: DPrm,
+}
+
+impl Snapshot {
+    pub fn version(&self) -> Version {
+        self.version
+    }
+}
+
+impl Deref for Snapshot {
+    type Target = DPrm;
+
+    fn deref(&self) -> &DPrm {
+        &self.dprm
+    }
+}
+
+/// A result together with the version of the roadmap it was computed on.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Versioned<T> {
+    pub version: Version,
+    pub value: T,
+}
+
+/// A DPrm shared between many readers and one writer at a time.
+/// Readers take the current snapshot and query it without holding any lock,
+/// so they never wait for an update and always see one consistent version.
+/// Updates are serialized, applied to a copy of the current snapshot and then published as the next version.
+/// The copy shares the vertices, edges and neighbors, and shares the per-edge and per-vertex counters in chunks
+/// and the lists of every obstacle, region and zone until the change writes to them. A publish copies the chunks
+/// and lists the change touches plus one pointer per chunk and per obstacle, not the whole roadmap.
+/// Queries that are still running on an older version finish on it.
+pub struct SharedDPrm {
+    current: RwLock<Arc<Snapshot>>,
+    update: Mutex<()>,
+}
+
+impl SharedDPrm {
+    pub fn new(dprm: DPrm) -> SharedDPrm {
+        SharedDPrm {
+            current: RwLock::new(Arc::new(Snapshot { version: 0, dprm })),
+            update: Mutex::new(()),
+        }
+    }
+
+    /// Returns the latest version. The lock is only held to clone the pointer.
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.current.read().expect("Snapshot lock poisoned").clone()
+    }
+
+    pub fn version(&self) -> Version {
+        self.snapshot().version
+    }
+
+    /// Runs the A* algorithm on the latest version.
+    pub fn run_astar(&self, start: &VertexIndex, end: &VertexIndex) -> Versioned<Option<DPrmPath>> {
+        let snapshot = self.snapshot();
+        Versioned { version: snapshot.version, value: snapshot.run_astar(start, end) }
+    }
+
+    /// Applies any change to a copy of the latest version and publishes it as the next version.
+    pub async fn update<R>(&self, change: impl FnOnce(&mut DPrm) -> R) -> Versioned<R> {
+        let _update = self.update.lock().await;
+        let snapshot = self.snapshot();
+        let mut dprm = snapshot.dprm.clone();
+        let value = change(&mut dprm);
+        Versioned { version: self.publish(snapshot.version, dprm), value }
+    }
+
+    /// Like `update`, but publishes nothing if the change returns None.
+    /// Whatever the change did to the copy before returning None is discarded.
+    pub async fn try_update<R>(&self, change: impl FnOnce(&mut DPrm) -> Option<R>) -> Option<Versioned<R>> {
+        let _update = self.update.lock().await;
+        let snapshot = self.snapshot();
+        let mut dprm = snapshot.dprm.clone();
+        let value = change(&mut dprm)?;
+        Some(Versioned { version: self.publish(snapshot.version, dprm), value })
+    }
+
+    /// Inserts the obstacle and publishes the next version, returning the newly blocked edges.
+    /// The blocked edges are searched on the latest version while readers keep using it.
+    /// Returns None and publishes nothing if the id is already in use.
+    pub async fn insert_obstacle(&self, obstacle: Obstacle) -> Option<Versioned<Vec<EdgeIndex>>> {
+        let _update = self.update.lock().await;
+        let snapshot = self.snapshot();
+        if snapshot.contains_obstacle(obstacle.id()) {
+            return None;
+        }
+        let blockings = snapshot.find_blocked_by_obstacle(obstacle).await;
+        let mut dprm = snapshot.dprm.clone();
+        let value = dprm.insert_blocked_by_obstacle(obstacle, blockings);
+        Some(Versioned { version: self.publish(snapshot.version, dprm), value })
+    }
+
+    /// Removes the obstacle and publishes the next version, returning the newly unblocked edges.
+    /// Returns None and publishes nothing if there is no obstacle with the id.
+    pub async fn remove_obstacle(&self, oid: ObstacleId) -> Option<Versioned<Vec<EdgeIndex>>> {
+        self.try_update(|dprm| dprm.contains_obstacle(oid).then(|| dprm.remove_obstacle(oid))).await
+    }
+
+    /// Replaces the current snapshot, must be called while holding the update lock.
+    fn publish(&self, previous: Version, dprm: DPrm) -> Version {
+        let version = previous + 1;
+        *self.current.write().expect("Snapshot lock poisoned") = Arc::new(Snapshot { version, dprm });
+        version
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::{Centroid, Rect};
+
+    fn cfg() -> PrmConfig {
+        PrmConfig::new(150, 100, 100, [7u8; 32], 2)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn readers_see_consistent_versions_during_updates() {
+        let dprm = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: Vec::new() }).await;
+        let n = dprm.vertices.len();
+        let obstacles: Vec<Obstacle> = (0..6)
+            .map(|i| Obstacle { rect: Rect::new((10.0 + 12.0 * i as f64, 20.0), (18.0 + 12.0 * i as f64, 80.0)), id: i })
+            .collect();
+
+        // The path length expected at every version
+        let mut expected = vec![dprm.run_astar(&0, &(n - 1)).map(|p| p.length)];
+        let mut sequential = dprm.clone();
+        for obstacle in obstacles.iter() {
+            let blockings = sequential.find_blocked_by_obstacle(*obstacle).await;
+            sequential.insert_blocked_by_obstacle(*obstacle, blockings);
+            expected.push(sequential.run_astar(&0, &(n - 1)).map(|p| p.length));
+        }
+
+        let shared = Arc::new(SharedDPrm::new(dprm));
+        let old = shared.snapshot();
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                tokio::task::spawn_blocking(move || {
+                    let mut seen = Vec::new();
+                    while seen.last().is_none_or(|(version, _)| *version < 6) {
+                        let result = shared.run_astar(&0, &(n - 1));
+                        seen.push((result.version, result.value.map(|p| p.length)));
+                    }
+                    seen
+                })
+            })
+            .collect();
+        for (i, obstacle) in obstacles.iter().enumerate() {
+            let inserted = shared.insert_obstacle(*obstacle).await.unwrap();
+            assert_eq!(inserted.version, i as Version + 1);
+        }
+        for reader in readers {
+            let seen = reader.await.unwrap();
+            assert!(seen.windows(2).all(|w| w[0].0 <= w[1].0));
+            for (version, length) in seen {
+                assert_eq!(length, expected[version as usize], "version {}", version);
+            }
+        }
+        assert_eq!(old.version(), 0);
+        assert_eq!(old.run_astar(&0, &(n - 1)).map(|p| p.length), expected[0]);
+        assert_eq!(shared.remove_obstacle(0).await.unwrap().version, 7);
+        // Refused updates publish no version
+        assert!(shared.insert_obstacle(obstacles[1]).await.is_none());
+        assert!(shared.remove_obstacle(0).await.is_none());
+        assert_eq!(shared.version(), 7);
+        assert_eq!(shared.snapshot().validate(), Vec::new());
+    }
+
+    #[tokio::test]
+    async fn updates_copy_only_what_they_change() {
+        let first = Obstacle { rect: Rect::new((10.0, 10.0), (20.0, 20.0)), id: 1 };
+        let dprm = DPrm::from_cfg(PrmConfig::new(3000, 100, 100, [7u8; 32], 2), ObstacleSet { obstacles: vec![first] }).await;
+        let shared = SharedDPrm::new(dprm);
+        let old = shared.snapshot();
+        // A speck on the middle of the last edge blocks only a few edges
+        let middle = old.edges[old.edges.len() - 1].line(&old.vertices).centroid();
+        let second = Obstacle { rect: Rect::new((middle.x() - 0.01, middle.y() - 0.01), (middle.x() + 0.01, middle.y() + 0.01)), id: 2 };
+        assert!(!shared.insert_obstacle(second).await.unwrap().value.is_empty());
+        let new = shared.snapshot();
+
+        let (old_view, new_view) = (&old.views[0], &new.views[0]);
+        assert!(Arc::ptr_eq(&old_view.blocked_per_obstacle[&1], &new_view.blocked_per_obstacle[&1]));
+        assert!(Arc::ptr_eq(&old_view.contained_per_obstacle[&1], &new_view.contained_per_obstacle[&1]));
+        // Only the chunks holding the edges blocked by the new obstacle are copied
+        let chunks = new_view.blockings_per_edge.shared_chunks(&new_view.blockings_per_edge);
+        let shared_chunks = old_view.blockings_per_edge.shared_chunks(&new_view.blockings_per_edge);
+        assert!(0 < shared_chunks && shared_chunks < chunks, "{} of {} chunks shared", shared_chunks, chunks);
+        assert_eq!(new.validate(), Vec::new());
+    }
+}
@@ -0,0 +1,125 @@
+use crate::prelude::*;
+use geo::{Coord, Line, Point, Rect};
+use plotters::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Time in the caller's units, such as seconds or ticks, the same as the clock of a DPrm.
+/// Speeds are in distance per unit of time.
+pub type Time = f64;
+
+/// An obstacle moving along a known trajectory, such as a scheduled AGV or a conveyor shuttle.
+/// The trajectory is a list of (time, center) waypoints in ascending time order, between which the
+/// obstacle moves in a straight line. It only exists from the first to the last waypoint.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct MovingObstacle {
+    pub width: f64,
+    pub height: f64,
+    pub trajectory: Vec<(Time, Point<f64>)>,
+    pub id: ObstacleId,
+}
+
+impl MovingObstacle {
+    pub fn new(width: f64, height: f64, trajectory: Vec<(Time, Point<f64>)>, id: ObstacleId) -> MovingObstacle {
+        MovingObstacle { width, height, trajectory, id }
+    }
+
+    /// Returns the obstacle at the given time, or None before its first or after its last waypoint.
+    pub fn at(&self, time: Time) -> Option<Obstacle> {
+        let i = self.trajectory.partition_point(|(t, _)| *t <= time);
+        let center = match i {
+            0 => return None,
+            i if i == self.trajectory.len() => {
+                let (t, center) = self.trajectory[i - 1];
+                if t < time {
+                    return None;
+                }
+                center
+            }
+            i => {
+                let (t0, p0) = self.trajectory[i - 1];
+                let (t1, p1) = self.trajectory[i];
+                let f = (time - t0) / (t1 - t0);
+                p0 + (p1 - p0) * f
+            }
+        };
+        let half = Coord { x: self.width / 2.0, y: self.height / 2.0 };
+        Some(Obstacle { rect: Rect::new(center.0 - half, center.0 + half), id: self.id })
+    }
+
+    /// Returns true if the point is within `clearance` of the obstacle at the given time.
+    pub fn contains_with_clearance(&self, point: &Point<f64>, time: Time, clearance: f64) -> bool {
+        self.at(time).is_some_and(|o| o.contains_with_clearance(point, clearance))
+    }
+
+    pub fn path(&self) -> PathElement<(f64, f64)> {
+        PathElement::new(self.trajectory.iter().map(|(_, p)| p.x_y()).collect::<Vec<_>>(), MAGENTA)
+    }
+}
+
+/// Parameters of a query in (vertex, time) space, see `DPrm::run_timed_astar`.
+/// Time is discretized into steps of `time_step`: the robot waits or moves for whole steps,
+/// and collisions with moving obstacles are checked at every step.
+/// The search gives up on paths arriving after `start_time + horizon`.
+/// Speed and time step must be positive and the horizon not negative, all of them finite.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TimedQuery {
+    pub start_time: Time,
+    pub speed: f64,
+    pub time_step: Time,
+    pub horizon: Time,
+}
+
+impl TimedQuery {
+    pub fn new(start_time: Time, speed: f64, time_step: Time, horizon: Time) -> TimedQuery {
+        assert!(start_time.is_finite(), "Start time must be finite, got {}", start_time);
+        assert!(speed.is_finite() && speed > 0.0, "Speed must be positive and finite, got {}", speed);
+        assert!(time_step.is_finite() && time_step > 0.0, "Time step must be positive and finite, got {}", time_step);
+        assert!(horizon.is_finite() && horizon >= 0.0, "Horizon must be finite and not negative, got {}", horizon);
+        TimedQuery { start_time, speed, time_step, horizon }
+    }
+
+    /// Returns true if the parameters are in the ranges `new` accepts, for queries built or deserialized field by field.
+    pub fn is_valid(&self) -> bool {
+        self.start_time.is_finite()
+            && self.speed.is_finite()
+            && self.speed > 0.0
+            && self.time_step.is_finite()
+            && self.time_step > 0.0
+            && self.horizon.is_finite()
+            && self.horizon >= 0.0
+    }
+
+    /// Number of whole steps needed to travel the distance.
+    pub(crate) fn steps(&self, distance: f64) -> u32 {
+        (distance / (self.speed * self.time_step)).ceil() as u32
+    }
+
+    pub(crate) fn time(&self, step: u32) -> Time {
+        self.start_time + step as Time * self.time_step
+    }
+
+    /// Position on the line after `step` of `steps` steps at the query speed.
+    pub(crate) fn position(&self, line: &Line<f64>, step: u32, steps: u32) -> Point<f64> {
+        let length = (line.dx().powi(2) + line.dy().powi(2)).sqrt();
+        let fraction = match steps {
+            0 => 1.0,
+            _ => (step as f64 * self.speed * self.time_step / length).min(1.0),
+        };
+        Point::from(line.start + (line.end - line.start) * fraction)
+    }
+}
+
+/// A vertex of a timed path and the time the robot is there.
+/// A vertex that appears twice in a row is a wait from the first time until the second.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TimedVertex {
+    pub vertex: Vertex,
+    pub time: Time,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TimedPath {
+    pub vertices: Vec<TimedVertex>,
+    /// Time of arrival at the last vertex.
+    pub arrival: Time,
+}
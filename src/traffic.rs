@@ -0,0 +1,211 @@
+use crate::chunked::ChunkedVec;
+use crate::dprm::serialize_sorted;
+use crate::prelude::*;
+use geo::{BoundingRect, Intersects, Line, Polygon};
+use plotters::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::f64::consts::TAU;
+use std::sync::Arc;
+
+pub type ZoneId = u128;
+
+/// Direction in which an edge is traversed, `Forward` goes from its lower to its higher vertex index.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+/// A one-way rule, such as an aisle that may only be driven in one direction.
+/// Edges crossing the polygon may only be traversed with a heading in the allowed range,
+/// which goes counter-clockwise from `min_heading` to `max_heading`, in radians from the positive x axis.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct DirectionZone {
+    pub polygon: Polygon<f64>,
+    pub min_heading: f64,
+    pub max_heading: f64,
+    pub id: ZoneId,
+}
+
+impl DirectionZone {
+    /// Headings may be any finite angles. They are taken modulo a full turn, so a `max_heading` below `min_heading`
+    /// wraps around, except that a `max_heading` at least a full turn above `min_heading` allows every heading.
+    pub fn new(polygon: Polygon<f64>, min_heading: f64, max_heading: f64, id: ZoneId) -> DirectionZone {
+        assert!(min_heading.is_finite() && max_heading.is_finite(), "Headings must be finite, got {} and {}", min_heading, max_heading);
+        DirectionZone { polygon, min_heading, max_heading, id }
+    }
+
+    /// Returns true if the heading lies in the allowed range.
+    pub fn allows(&self, heading: f64) -> bool {
+        self.max_heading - self.min_heading >= TAU
+            || (heading - self.min_heading).rem_euclid(TAU) <= (self.max_heading - self.min_heading).rem_euclid(TAU)
+    }
+
+    /// Returns the directions in which the edge may not be traversed, empty if it does not cross the zone.
+    pub fn blocked_directions(&self, edge: &Line<f64>) -> Vec<Direction> {
+        let crosses = match self.polygon.bounding_rect() {
+            Some(bounds) => bounds.intersects(edge) && self.polygon.intersects(edge),
+            None => false,
+        };
+        if !crosses {
+            return Vec::new();
+        }
+        let heading = edge.dy().atan2(edge.dx());
+        let mut blocked = Vec::new();
+        if !self.allows(heading) {
+            blocked.push(Direction::Forward);
+        }
+        if !self.allows(heading + TAU / 2.0) {
+            blocked.push(Direction::Backward);
+        }
+        blocked
+    }
+
+    pub fn shape(&self) -> plotters::element::Polygon<(f64, f64)> {
+        plotters::element::Polygon::new(
+            self.polygon.exterior().points().map(|p| p.x_y()).collect::<Vec<_>>(),
+            GREEN.mix(0.2).filled(),
+        )
+    }
+}
+
+/// The directions of edges blocked by one-way rules, kept apart from the obstacle blockings
+/// so that the rules can be switched off without losing them.
+/// The counters are empty while there are no zones.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub(crate) struct TrafficLayer {
+    pub(crate) enabled: bool,
+    pub(crate) zones: Vec<DirectionZone>,
+    #[serde(serialize_with = "serialize_sorted")]
+    pub(crate) blocked_per_zone: HashMap<ZoneId, Arc<Vec<(EdgeIndex, Direction)>>>,
+    pub(crate) blockings_forward: ChunkedVec<u16>,
+    pub(crate) blockings_backward: ChunkedVec<u16>,
+}
+
+impl Default for TrafficLayer {
+    fn default() -> TrafficLayer {
+        TrafficLayer {
+            enabled: true,
+            zones: Vec::new(),
+            blocked_per_zone: HashMap::new(),
+            blockings_forward: ChunkedVec::default(),
+            blockings_backward: ChunkedVec::default(),
+        }
+    }
+}
+
+impl TrafficLayer {
+    /// Returns true if the rules allow traversing the edge in the direction.
+    pub(crate) fn allows(&self, edge: EdgeIndex, direction: Direction) -> bool {
+        !self.enabled || self.counters(direction).get(edge).copied().unwrap_or(0) == 0
+    }
+
+    fn counters(&self, direction: Direction) -> &ChunkedVec<u16> {
+        match direction {
+            Direction::Forward => &self.blockings_forward,
+            Direction::Backward => &self.blockings_backward,
+        }
+    }
+
+    fn counter(&mut self, edge: EdgeIndex, direction: Direction) -> &mut u16 {
+        match direction {
+            Direction::Forward => &mut self.blockings_forward[edge],
+            Direction::Backward => &mut self.blockings_backward[edge],
+        }
+    }
+
+    pub(crate) fn contains(&self, id: ZoneId) -> bool {
+        self.zones.iter().any(|z| z.id == id)
+    }
+
+    /// Records the zone and the directions it blocks, returning the newly blocked directions.
+    pub(crate) fn insert(&mut self, zone: DirectionZone, blocked: Vec<(EdgeIndex, Direction)>, num_edges: usize) -> Vec<(EdgeIndex, Direction)> {
+        if self.blockings_forward.is_empty() {
+            self.blockings_forward = ChunkedVec::from_elem(0, num_edges);
+            self.blockings_backward = ChunkedVec::from_elem(0, num_edges);
+        }
+        let mut newly_blocked = Vec::new();
+        for (edge, direction) in blocked.iter() {
+            let count = self.counter(*edge, *direction);
+            *count = count.checked_add(1).expect("Too many zones block the same edge");
+            if *count == 1 {
+                newly_blocked.push((*edge, *direction));
+            }
+        }
+        self.blocked_per_zone.insert(zone.id, Arc::new(blocked));
+        self.zones.push(zone);
+        newly_blocked
+    }
+
+    /// Forgets the zone, returning the newly allowed directions.
+    pub(crate) fn remove(&mut self, id: ZoneId) -> Vec<(EdgeIndex, Direction)> {
+        let mut newly_allowed = Vec::new();
+        match self.blocked_per_zone.remove(&id) {
+            Some(blocked) => {
+                for (edge, direction) in blocked.iter().copied() {
+                    let count = self.counter(edge, direction);
+                    if *count == 0 {
+                        // Inconsistent state, see validate
+                        println!("Edge {} of zone {} is not blocked {:?}", edge, id, direction);
+                        continue;
+                    }
+                    *count -= 1;
+                    if *count == 0 {
+                        newly_allowed.push((edge, direction));
+                    }
+                }
+            }
+            None => println!("Zone {} not found", id),
+        }
+        self.zones.retain(|z| z.id != id);
+        if self.zones.is_empty() {
+            self.blockings_forward = ChunkedVec::default();
+            self.blockings_backward = ChunkedVec::default();
+        }
+        newly_allowed
+    }
+
+    /// Checks the blocked directions against the zones and the counters, see `DPrm::validate`.
+    pub(crate) fn validate(&self, num_edges: usize) -> Vec<Inconsistency> {
+        let mut inconsistencies = Vec::new();
+        let mut seen = HashSet::new();
+        for z in self.zones.iter() {
+            if !seen.insert(z.id) {
+                inconsistencies.push(Inconsistency::DuplicateZone(z.id));
+            } else if !self.blocked_per_zone.contains_key(&z.id) {
+                inconsistencies.push(Inconsistency::MissingZoneBlockings(z.id));
+            }
+        }
+        let mut ids: Vec<&ZoneId> = self.blocked_per_zone.keys().collect();
+        ids.sort();
+        let mut expected_forward = vec![0; num_edges];
+        let mut expected_backward = vec![0; num_edges];
+        for id in ids {
+            if !seen.contains(id) {
+                inconsistencies.push(Inconsistency::UnknownZone(*id));
+            }
+            for (edge, direction) in self.blocked_per_zone[id].iter() {
+                if *edge >= num_edges {
+                    inconsistencies.push(Inconsistency::UnknownZoneEdge { zone: *id, edge: *edge });
+                    continue;
+                }
+                match direction {
+                    Direction::Forward => expected_forward[*edge] += 1,
+                    Direction::Backward => expected_backward[*edge] += 1,
+                }
+            }
+        }
+        for (direction, expected_counts) in [(Direction::Forward, expected_forward), (Direction::Backward, expected_backward)] {
+            let counters = self.counters(direction);
+            for edge in 0..num_edges.max(counters.len()) {
+                let expected = expected_counts.get(edge).copied().unwrap_or(0);
+                let actual = counters.get(edge).copied().unwrap_or(0) as usize;
+                if expected != actual {
+                    inconsistencies.push(Inconsistency::DirectionBlockingCount { edge, direction, expected, actual });
+                }
+            }
+        }
+        inconsistencies
+    }
+}
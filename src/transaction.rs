@@ -0,0 +1,147 @@
+use crate::prelude::*;
+use std::ops::Deref;
+
+/// Everything needed to put a removed obstacle back exactly as it was.
+/// `position` is its index among the active obstacles, None if its layer was disabled.
+pub(crate) struct RemovedObstacle {
+    pub(crate) obstacle: Obstacle,
+    pub(crate) blockings: Blockings,
+    pub(crate) position: Option<usize>,
+    pub(crate) expiry: Option<Time>,
+    pub(crate) layer: Option<LayerId>,
+}
+
+enum Undo {
+    Insert(ObstacleId),
+    Remove(RemovedObstacle),
+}
+
+/// A what-if change to the obstacles of a DPrm, started with `DPrm::begin`.
+/// Obstacles are inserted and removed through the transaction, and all `&self` queries of the DPrm
+/// can be run on it in between. `commit` keeps the changes, `rollback` or dropping the transaction
+/// undoes them in reverse order. Only the blockings of the changed obstacles are touched,
+/// so both cost as much as the changes themselves.
+pub struct Transaction<'a> {
+    dprm: &'a mut DPrm,
+    undo: Vec<Undo>,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(dprm: &'a mut DPrm) -> Transaction<'a> {
+        Transaction { dprm, undo: Vec::new() }
+    }
+
+    /// Inserts the obstacle like `DPrm::insert_blocked_by_obstacle`, returning the newly blocked edges.
+    pub fn insert_blocked_by_obstacle(&mut self, obstacle: Obstacle, blockings: Blockings) -> Vec<EdgeIndex> {
+        if self.dprm.contains_obstacle(obstacle.id()) {
+            println!("Obstacle {} already exists", obstacle.id());
+            return Vec::new();
+        }
+        let newly_blocked_edges = self.dprm.insert_blocked_by_obstacle(obstacle, blockings);
+        if self.dprm.contains_obstacle(obstacle.id()) {
+            self.undo.push(Undo::Insert(obstacle.id()));
+        }
+        newly_blocked_edges
+    }
+
+    /// Removes the obstacle like `DPrm::remove_obstacle`, returning the newly unblocked edges.
+    pub fn remove_obstacle(&mut self, oid: ObstacleId) -> Vec<EdgeIndex> {
+        match self.dprm.take_obstacle(oid) {
+            Some((removed, newly_unblocked_edges)) => {
+                self.undo.push(Undo::Remove(removed));
+                newly_unblocked_edges
+            }
+            None => {
+                println!("Obstacle {} not found", oid);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Keeps the changes.
+    pub fn commit(mut self) {
+        self.undo.clear();
+    }
+
+    /// Undoes the changes, the same as dropping the transaction.
+    pub fn rollback(self) {}
+}
+
+impl Deref for Transaction<'_> {
+    type Target = DPrm;
+
+    fn deref(&self) -> &DPrm {
+        self.dprm
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        while let Some(undo) = self.undo.pop() {
+            match undo {
+                Undo::Insert(oid) => {
+                    self.dprm.remove_obstacle(oid);
+                }
+                Undo::Remove(removed) => self.dprm.restore_obstacle(removed),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dprm::tests::assert_equivalent;
+    use geo::Rect;
+
+    fn cfg() -> PrmConfig {
+        PrmConfig::new(150, 100, 100, [7u8; 32], 2)
+    }
+
+    #[tokio::test]
+    async fn transactions_roll_back_exactly_and_commit() {
+        let wall = Obstacle { rect: Rect::new((45.0, 0.0), (55.0, 60.0)), id: 1 };
+        let post = Obstacle { rect: Rect::new((10.0, 80.0), (15.0, 85.0)), id: 2 };
+        let mut dprm = DPrm::from_cfg(cfg(), ObstacleSet { obstacles: vec![wall, post] }).await;
+        dprm.set_obstacle_expiry(1, Some(50.0));
+        let before = bincode::serialize(&dprm).unwrap();
+        let n = dprm.vertices.len();
+        let racks = [
+            Obstacle { rect: Rect::new((20.0, 20.0), (30.0, 25.0)), id: 3 },
+            Obstacle { rect: Rect::new((20.0, 40.0), (30.0, 45.0)), id: 4 },
+            Obstacle { rect: Rect::new((20.0, 60.0), (30.0, 65.0)), id: 5 },
+        ];
+        let mut changed = ObstacleSet { obstacles: vec![post] };
+        let mut txn = dprm.begin();
+        for rack in racks {
+            let blockings = txn.find_blocked_by_obstacle(rack).await;
+            txn.insert_blocked_by_obstacle(rack, blockings);
+            changed.add(rack);
+        }
+        txn.remove_obstacle(1);
+        assert_eq!(txn.validate(), Vec::new());
+        let what_if = txn.run_astar(&0, &(n - 1)).map(|p| p.length);
+        txn.rollback();
+        assert_eq!(bincode::serialize(&dprm).unwrap(), before);
+
+        // Dropping also rolls back
+        {
+            let mut txn = dprm.begin();
+            txn.remove_obstacle(2);
+        }
+        assert_eq!(bincode::serialize(&dprm).unwrap(), before);
+
+        let mut txn = dprm.begin();
+        for rack in racks {
+            let blockings = txn.find_blocked_by_obstacle(rack).await;
+            txn.insert_blocked_by_obstacle(rack, blockings);
+        }
+        txn.remove_obstacle(1);
+        txn.commit();
+        assert_eq!(dprm.validate(), Vec::new());
+        assert_eq!(dprm.obstacle_expiry(1), None);
+        let fresh = DPrm::from_cfg(cfg(), changed).await;
+        assert_equivalent(&dprm, &fresh).unwrap();
+        assert_eq!(dprm.run_astar(&0, &(n - 1)).map(|p| p.length), what_if);
+    }
+}